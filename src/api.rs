@@ -1,5 +1,6 @@
 pub mod base;
 pub mod bundle;
+pub mod patch;
 pub mod rest;
 mod capability;
 
@@ -1,18 +1,24 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::format;
 use bson::Document;
+use chrono::{DateTime, Utc};
+use ksuid::Ksuid;
 use log::{debug, warn};
 use rocksdb::WriteBatch;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use url::form_urlencoded;
 use rocket::request::{FromRequest, Outcome};
 use std::convert::Infallible;
 use rawbson::Doc;
 use rocket::Request;
+use rocket::http::Status;
 
-use crate::api::bundle;
-use crate::api::bundle::{BundleType, Method, RequestBundle, SearchSet};
+use crate::api::{bundle, patch};
+use crate::api::bundle::{BundleType, HistorySet, Method, RequestBundle, ResponseBundle, ResponseEntry, SearchSet};
 use crate::api::capability::gen_capability_stmt;
-use crate::barn::Barn;
+use crate::barn::{Barn, StagedResources, VersionChecks};
+use crate::config::{Config, Versioning};
 use crate::errors::{EvalError, IssueSeverity, IssueType, RaError};
 use crate::rapath::expr::Ast;
 use crate::rapath::parser::parse;
@@ -21,22 +27,36 @@ use crate::res_schema::{parse_res_def, parse_search_param, SchemaDef};
 use crate::ResourceDef;
 use crate::search::{ComparisonOperator, Filter, Modifier};
 use crate::search::executor::execute_search_query;
+use crate::search::include::{find_included, IncludeParam, parse_include};
 use crate::search::filter_converter::param_to_filter;
+use crate::utils;
+use crate::utils::bson_utils;
 
 pub struct ApiBase {
     pub(crate) db: Barn,
     pub(crate) schema: SchemaDef,
-    pub(crate) base_url: String
+    pub(crate) base_url: String,
+    pub(crate) config: Config
 }
 
 pub enum RaResponse {
     Success(Option<Document>),
     Created(Document),
-    SearchResult(SearchSet)
+    Updated(Document),
+    Deleted,
+    Resource(Document),
+    NotModified(Document),
+    SearchResult(SearchSet),
+    History(HistorySet),
+    Bundle(ResponseBundle)
 }
 
+#[derive(Default)]
 pub struct ConditionalHeaders<'r> {
     pub if_none_exist: Option<&'r str>,
+    pub if_match: Option<&'r str>,
+    pub if_none_match: Option<&'r str>,
+    pub if_modified_since: Option<&'r str>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -75,17 +95,30 @@ pub struct SearchQuery<'r> {
     pub params: Vec<(&'r str, &'r str)>,
     pub sort: Option<&'r str>,
     pub count: u32,
-    pub include: Option<&'r str>,
-    pub revinclude: Option<&'r str>,
+    /// number of matches to skip
+    pub offset: u32,
+    /// the continuation token given in the next or previous link of a searchset
+    pub page: Option<&'r str>,
+    /// the _include parameters as (name, value) pairs, the name may carry the iterate modifier
+    pub include: Vec<(&'r str, &'r str)>,
+    /// the _revinclude parameters as (name, value) pairs
+    pub revinclude: Vec<(&'r str, &'r str)>,
     pub total: Total,
     pub contained: Contained,
     pub contained_type: ContainedType,
-    pub summary: bool,
+    pub summary: Summary,
     pub elements: bool,
     pub ignore_unknown_params: bool
 }
 
 
+#[derive(Debug)]
+pub struct HistoryQuery<'r> {
+    pub count: u32,
+    pub since: Option<&'r str>,
+    pub at: Option<&'r str>
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum ReturnContent {
     Minimal,
@@ -100,6 +133,15 @@ pub enum Total {
     Accurate
 }
 
+#[derive(Debug, Eq, PartialEq)]
+pub enum Summary {
+    True,
+    Text,
+    Data,
+    Count,
+    False
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum Contained {
     DoNotReturn,
@@ -123,6 +165,18 @@ impl From<&str> for Total {
     }
 }
 
+impl From<&str> for Summary {
+    fn from(s: &str) -> Self {
+        match s {
+            "true" => Summary::True,
+            "text" => Summary::Text,
+            "data" => Summary::Data,
+            "count" => Summary::Count,
+            _ => Summary::False
+        }
+    }
+}
+
 impl From<&str> for Contained {
     fn from(s: &str) -> Self {
         match s {
@@ -162,6 +216,57 @@ pub struct ResponseHints {
     pub elements: bool,
 }
 
+impl<'r> SearchQuery<'r> {
+    pub fn new(params: Vec<(&'r str, &'r str)>) -> Self {
+        SearchQuery {
+            params,
+            sort: None,
+            count: 20,
+            offset: 0,
+            page: None,
+            include: Vec::new(),
+            revinclude: Vec::new(),
+            total: Total::None,
+            contained: Contained::DoNotReturn,
+            contained_type: ContainedType::Container,
+            summary: Summary::False,
+            elements: false,
+            ignore_unknown_params: false
+        }
+    }
+
+    /// builds the query string of a searchset's link, the page token is used in place of the offset
+    pub fn to_query_string(&self, page: Option<&str>) -> String {
+        let mut qs = form_urlencoded::Serializer::new(String::new());
+        for (k, v) in &self.params {
+            qs.append_pair(k, v);
+        }
+        if let Some(sort) = self.sort {
+            qs.append_pair("_sort", sort);
+        }
+        for (k, v) in self.include.iter().chain(self.revinclude.iter()) {
+            qs.append_pair(k, v);
+        }
+        match self.total {
+            Total::Accurate => { qs.append_pair("_total", "accurate"); },
+            Total::Estimate => { qs.append_pair("_total", "estimate"); },
+            Total::None => {}
+        }
+        qs.append_pair("_count", &self.count.to_string());
+        match page {
+            Some(page) => {
+                qs.append_pair("_page", page);
+            },
+            None => {
+                if self.offset > 0 {
+                    qs.append_pair("_offset", &self.offset.to_string());
+                }
+            }
+        }
+        qs.finish()
+    }
+}
+
 impl ResponseHints {
     pub fn default() -> Self {
         ResponseHints{rturn: ReturnContent::Minimal, pretty: false, elements: false, summary: false}
@@ -191,53 +296,297 @@ impl OperationOutcome {
 
 impl ApiBase {
     pub fn new(db: Barn, base_url: String) -> Result<Self, RaError> {
+        let mut config = Config::default();
+        config.base_url = base_url;
+        ApiBase::new_with_config(db, config)
+    }
+
+    pub fn new_with_config(db: Barn, config: Config) -> Result<Self, RaError> {
         let schema = db.build_schema_def()?;
-        Ok(ApiBase{db, schema, base_url})
+        let base_url = config.base_url.clone();
+        Ok(ApiBase{db, schema, base_url, config})
     }
 
-    fn transaction(&self, val: Value) -> Result<RaResponse, RaError> {
+    fn transaction(&self, mut val: Value, hints: &ResponseHints) -> Result<RaResponse, RaError> {
         debug!("validating the transaction bundle");
         self.schema.validate(&val)?;
-        let req_bundle = RequestBundle::from(val)?;
+        self.resolve_conditional_urls(&mut val)?;
+        if let Some(entries) = val.get_mut("entry").and_then(|e| e.as_array_mut()) {
+            for item in entries {
+                if let Some(resource) = item.get_mut("resource") {
+                    self.resolve_conditional_refs(resource, "")?;
+                }
+            }
+        }
+        let existing = self.find_existing_for_conditional_creates(&val)?;
+        let req_bundle = RequestBundle::from_with_existing(val, &existing)?;
         debug!("processing transaction bundle");
+        let representation = hints.rturn == ReturnContent::Representation;
+        // the responses are placed in the order of the entries in the request bundle
+        let mut responses: Vec<Option<ResponseEntry>> = (0..req_bundle.entries.len()).map(|_| None).collect();
+        let mut reads = Vec::new();
+        let mut targets = HashSet::new();
         let mut to_be_indexed = Vec::new();
+        // the resources that are not yet saved, but may be referred by other resources of the transaction
+        let mut staged = StagedResources::new();
         let mut wb = WriteBatch::default();
+        // the versions of the updated and deleted resources must still be current at the time of saving the batch
+        let mut checks = VersionChecks::new();
+        let keep_history = self.config.versioning != Versioning::No_version;
         for e in req_bundle.entries {
+            if e.req_method != Method::Get && e.req_method != Method::Head && !e.exists {
+                if !targets.insert(e.ra_id.to_base62()) {
+                    return Err(RaError::bad_req(format!("resource {} appears more than once in the transaction", &e.req_url)));
+                }
+            }
+
+            let entry;
             match e.req_method {
                 Method::Delete => {
-
+                    let (res_name, _, _) = bundle::parse_req_url(&e.req_url)?;
+                    let rd = self.schema.get_res_def_by_name(res_name)?;
+                    let if_match = parse_etag(e.if_match.as_deref())?;
+                    self.db.delete_batch(&e.ra_id, rd, &mut wb, &mut checks, &self.schema, keep_history, if_match)?;
+                    entry = ResponseEntry::new("204 No Content");
                 },
                 Method::Post => {
-                    let data = e.resource;
-                    let rd = self.get_res_def(&data)?;
-                    let (_, doc_bytes, db_id) = self.db.insert_batch(&e.ra_id, rd, data, &mut wb, &self.schema, true)?;
-                    to_be_indexed.push((db_id, doc_bytes, rd));
+                    if e.exists {
+                        debug!("skipping the creation of existing resource {}", &e.full_url);
+                        let rd = self.get_res_def(&e.resource)?;
+                        let doc = self.db.read(rd, &e.ra_id.to_base62())?;
+                        entry = ResponseEntry::from_doc("200 OK", &doc, true, representation);
+                    }
+                    else {
+                        let data = e.resource;
+                        let rd = self.get_res_def(&data)?;
+                        // indexing is deferred till all the resources are staged, the search param expressions
+                        // may resolve() references to resources created later in the transaction
+                        let (doc, doc_bytes, db_id) = self.db.insert_batch(&e.ra_id, rd, data, &mut wb, &self.schema, true)?;
+                        staged.insert(db_id, doc_bytes);
+                        to_be_indexed.push((db_id, rd));
+                        entry = ResponseEntry::from_doc("201 Created", &doc, true, representation);
+                    }
                 },
                 Method::Put => {
-
+                    let data = e.resource;
+                    let rd = self.get_res_def(&data)?;
+                    let if_match = parse_etag(e.if_match.as_deref())?;
+                    self.check_version_aware(if_match)?;
+                    let (doc, created) = self.db.update_batch(&e.ra_id, rd, data, &mut wb, &mut checks, &self.schema, keep_history, self.config.update_create, if_match, Some(&staged))?;
+                    stage(&mut staged, rd, &e.ra_id, &doc)?;
+                    let status = if created { "201 Created" } else { "200 OK" };
+                    entry = ResponseEntry::from_doc(status, &doc, true, representation);
                 },
                 Method::Patch => {
-
+                    let (res_name, id, _) = bundle::parse_req_url(&e.req_url)?;
+                    let rd = self.schema.get_res_def_by_name(res_name)?;
+                    let patch = patch::get_patch_from_entry(&e.resource)?;
+                    let current = self.db.read(rd, id)?;
+                    let mut if_match = parse_etag(e.if_match.as_deref())?;
+                    self.check_version_aware(if_match)?;
+                    if let None = if_match {
+                        // the patch must be applied on the version that was read
+                        if_match = Some(bson_utils::get_int(&current, "meta.versionId") as u32);
+                    }
+                    let doc = self.apply_patch(rd, current, &patch)?;
+                    let (doc, _) = self.db.update_batch(&e.ra_id, rd, doc, &mut wb, &mut checks, &self.schema, keep_history, false, if_match, Some(&staged))?;
+                    stage(&mut staged, rd, &e.ra_id, &doc)?;
+                    entry = ResponseEntry::from_doc("200 OK", &doc, true, representation);
                 },
-                Method::Get => {
+                Method::Get | Method::Head => {
+                    // reads are processed after saving the changes made by the other entries
+                    reads.push(e);
+                    continue;
+                }
+            }
+            responses[e.index] = Some(entry);
+        }
 
-                },
-                Method::Head => {
+        debug!("indexing the resources created in the transaction");
+        for (db_id, rd) in to_be_indexed {
+            let doc = staged.get(&db_id).unwrap();
+            self.db.index_searchparams(&mut wb, &db_id, doc, rd, &self.schema, Some(&staged))?;
+        }
+        // resources and their index rows are committed together
+        self.db.save_batch(wb, &checks)?;
+
+        for e in reads {
+            let head = e.req_method == Method::Head;
+            let resp = self.process_request(e.req_method, &e.req_url, None, &ConditionalHeaders::default());
+            let mut entry = to_response_entry(resp, representation);
+            if head {
+                entry.resource = None;
+            }
+            responses[e.index] = Some(entry);
+        }
+
+        let mut rb = ResponseBundle::new(BundleType::TransactionResponse);
+        rb.entries = responses.into_iter().map(|r| r.unwrap()).collect();
+        Ok(RaResponse::Bundle(rb))
+    }
+
+    /// replaces the conditional URLs (e.g Patient?identifier=http://acme.org|123) in the requests of the
+    /// transaction's updates, deletes and patches with the URLs of the matched resources. A conditional
+    /// update that doesn't match any resource is turned into a create
+    fn resolve_conditional_urls(&self, val: &mut Value) -> Result<(), RaError> {
+        let entries = val.get_mut("entry").and_then(|e| e.as_array_mut());
+        if let None = entries {
+            return Ok(());
+        }
+
+        for item in entries.unwrap() {
+            let req_method = item.pointer("/request/method").and_then(|m| m.as_str()).unwrap_or("").to_string();
+            let req_url = item.pointer("/request/url").and_then(|u| u.as_str()).unwrap_or("").to_string();
+            let pos = req_url.find('?');
+            if pos.is_none() || (req_method != "PUT" && req_method != "DELETE" && req_method != "PATCH") {
+                continue;
+            }
+
+            let (enabled, interaction) = match req_method.as_str() {
+                "DELETE" => (self.config.conditional_delete, "delete"),
+                _ => (self.config.conditional_update, "update")
+            };
+            if !enabled {
+                return Err(RaError::bad_req(format!("conditional {} is not supported", interaction)));
+            }
 
+            let pos = pos.unwrap();
+            let res_name = &req_url[..pos];
+            let rd = self.schema.get_res_def_by_name(res_name)?;
+            let params = parse_query_string(&req_url[pos + 1..]);
+            let params = params.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            let matched = self.find_single_match(rd, params)?;
+            let request = item.get_mut("request").unwrap().as_object_mut().unwrap();
+            match matched {
+                Some(doc) => {
+                    request.insert(String::from("url"), Value::String(format!("{}/{}", res_name, doc.get_str("id")?)));
+                },
+                None => {
+                    if req_method != "PUT" {
+                        return Err(RaError::not_found(format!("no {} resource matches the conditional URL {}", res_name, &req_url)));
+                    }
+                    debug!("no matches found for the conditional update {}, creating a new {}", &req_url, res_name);
+                    request.insert(String::from("method"), Value::String(String::from("POST")));
+                    request.insert(String::from("url"), Value::String(res_name.to_string()));
                 }
             }
         }
 
-        self.db.save_batch(wb)?;
-        if !to_be_indexed.is_empty() {
-            debug!("indexing after saving data from batch");
-            let mut wb = WriteBatch::default();
-            for (db_id, doc, rd) in to_be_indexed {
-                self.db.index_searchparams(&mut wb, &db_id, &doc, rd, &self.schema)?;
+        Ok(())
+    }
+
+    /// replaces the conditional references (e.g Patient?identifier=http://acme.org|123) in the
+    /// given resource with the references of the matched resources
+    fn resolve_conditional_refs(&self, v: &mut Value, key: &str) -> Result<(), RaError> {
+        match v {
+            Value::String(s) => {
+                if key != "reference" {
+                    return Ok(());
+                }
+                if let Some(pos) = s.find('?') {
+                    let res_name = &s[..pos];
+                    // only the relative references of the form Type?query are conditional, the others
+                    // (e.g http://other.org/fhir/Patient?identifier=x) are left as they are
+                    if res_name.contains('/') || res_name.contains(':') || !self.schema.resources.contains_key(res_name) {
+                        return Ok(());
+                    }
+                    let rd = self.schema.get_res_def_by_name(res_name)?;
+                    let params = parse_query_string(&s[pos + 1..]);
+                    let params = params.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+                    let matched = self.find_single_match(rd, params)?;
+                    if let None = matched {
+                        return Err(RaError::precondition_failed(IssueType::Not_found, format!("no resource matches the conditional reference {}", s)));
+                    }
+                    let resolved = format!("{}/{}", res_name, matched.unwrap().get_str("id")?);
+                    *s = resolved;
+                }
+            },
+            Value::Object(m) => {
+                for (k, o) in m {
+                    self.resolve_conditional_refs(o, k)?;
+                }
+            },
+            Value::Array(a) => {
+                for i in a {
+                    self.resolve_conditional_refs(i, key)?;
+                }
+            },
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// processes each entry of the batch independently, a failed entry doesn't affect the other entries
+    fn batch(&self, val: Value, hints: &ResponseHints) -> Result<RaResponse, RaError> {
+        debug!("processing batch bundle");
+        let mut rb = ResponseBundle::new(BundleType::BatchResponse);
+        let entries = val.get("entry").and_then(|e| e.as_array());
+        if let Some(entries) = entries {
+            for item in entries {
+                let resp = self.process_batch_entry(item);
+                let mut entry = to_response_entry(resp, hints.rturn == ReturnContent::Representation);
+                if item.pointer("/request/method").and_then(|m| m.as_str()) == Some("HEAD") {
+                    entry.resource = None;
+                }
+                rb.entries.push(entry);
             }
-            self.db.save_batch(wb)?;
         }
-        Ok(RaResponse::Success(None))
+
+        Ok(RaResponse::Bundle(rb))
+    }
+
+    fn process_batch_entry(&self, item: &Value) -> Result<RaResponse, RaError> {
+        let req_method = item.pointer("/request/method").and_then(|m| m.as_str());
+        let req_url = item.pointer("/request/url").and_then(|u| u.as_str());
+        if req_method.is_none() || req_url.is_none() {
+            return Err(RaError::bad_req("missing method or url in the request of the entry"));
+        }
+
+        let req_method = Method::from(req_method.unwrap())?;
+        let get_header = |name: &str| item.pointer(&format!("/request/{}", name)).and_then(|h| h.as_str());
+        let ch = ConditionalHeaders{if_none_exist: get_header("ifNoneExist"), if_match: get_header("ifMatch"),
+            if_none_match: get_header("ifNoneMatch"), if_modified_since: get_header("ifModifiedSince")};
+
+        self.process_request(req_method, req_url.unwrap(), item.get("resource"), &ch)
+    }
+
+    /// processes a request given in an entry of a batch or a transaction
+    fn process_request(&self, req_method: Method, req_url: &str, resource: Option<&Value>, ch: &ConditionalHeaders) -> Result<RaResponse, RaError> {
+        let (path, query) = match req_url.find('?') {
+            Some(pos) => (&req_url[..pos], Some(&req_url[pos + 1..])),
+            None => (req_url, None)
+        };
+        let parts: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        let res_name = parts[0];
+        let params = query.map_or(Vec::new(), |q| parse_query_string(q));
+        let params: Vec<(&str, &str)> = params.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+
+        if resource.is_none() && (req_method == Method::Post || req_method == Method::Put || req_method == Method::Patch) {
+            return Err(RaError::bad_req(format!("missing resource in the entry with request URL {}", req_url)));
+        }
+
+        match (req_method, parts.len()) {
+            (Method::Post, 1) => {
+                let resource = resource.unwrap();
+                match ch.if_none_exist {
+                    Some(if_none_exist) => self.create_if_none_exist(res_name, resource, if_none_exist),
+                    None => self.create(res_name, resource)
+                }
+            },
+            (Method::Put, 1) => self.conditional_update(res_name, &SearchQuery::new(params), resource.unwrap(), ch.if_match),
+            (Method::Put, 2) => self.update(res_name, parts[1], resource.unwrap(), ch.if_match),
+            (Method::Delete, 1) => self.conditional_delete(res_name, &SearchQuery::new(params), ch.if_match),
+            (Method::Delete, 2) => self.delete(res_name, parts[1], ch.if_match),
+            (Method::Patch, 2) => {
+                let patch = patch::get_patch_from_entry(&bson::to_document(resource.unwrap())?)?;
+                self.patch(res_name, parts[1], &patch, ch.if_match)
+            },
+            (Method::Get, 1) | (Method::Head, 1) => self.search_query(res_name, &SearchQuery::new(params), &ResponseHints::default()),
+            (Method::Get, 2) | (Method::Head, 2) => Ok(self.conditional_read(self.read(res_name, parts[1])?, ch)),
+            (Method::Get, 4) | (Method::Head, 4) if parts[2] == "_history" => Ok(self.conditional_read(self.vread(res_name, parts[1], parts[3])?, ch)),
+            _ => Err(RaError::bad_req(format!("unsupported request URL {}", req_url)))
+        }
     }
 
     pub fn create(&self, res_name: &str, val: &Value) -> Result<RaResponse, RaError> {
@@ -253,7 +602,247 @@ impl ApiBase {
         Ok(RaResponse::Created(doc))
     }
 
+    /// creates the resource only if there are no matches for the given search condition
+    pub fn create_if_none_exist(&self, res_name: &str, val: &Value, if_none_exist: &str) -> Result<RaResponse, RaError> {
+        let rd = self.schema.get_res_def_by_name(res_name)?;
+        if !self.config.conditional_create {
+            return Err(RaError::bad_req("conditional create is not supported"));
+        }
+        let params = parse_query_string(if_none_exist);
+        let params = params.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        let matched = self.find_single_match(rd, params)?;
+        if let Some(doc) = matched {
+            debug!("found an existing {} matching {}, skipping create", res_name, if_none_exist);
+            return Ok(RaResponse::Resource(doc));
+        }
+
+        self.create(res_name, val)
+    }
+
+    /// updates the resource matching the given search parameters, creates a new one if there are no matches
+    pub fn conditional_update(&self, res_name: &str, query: &SearchQuery, val: &Value, if_match: Option<&str>) -> Result<RaResponse, RaError> {
+        if !self.config.conditional_update {
+            return Err(RaError::bad_req("conditional update is not supported"));
+        }
+
+        let rd = self.schema.get_res_def_by_name(res_name)?;
+        let matched = self.find_single_match(rd, query.params.clone())?;
+        let body_id = val.get("id").map(|v| v.as_str().unwrap_or(""));
+        if let None = matched {
+            if let Some(body_id) = body_id {
+                debug!("no matches found for conditional update, updating {}/{}", res_name, body_id);
+                return self.update(res_name, body_id, val, if_match);
+            }
+            debug!("no matches found for conditional update, creating a new {}", res_name);
+            return self.create(res_name, val);
+        }
+
+        let matched = matched.unwrap();
+        let id = matched.get_str("id")?;
+        if let Some(body_id) = body_id {
+            if body_id != id {
+                return Err(RaError::bad_req(format!("id {} in the resource doesn't match with the id {} of the matched resource", body_id, id)));
+            }
+            return self.update(res_name, id, val, if_match);
+        }
+
+        let mut val = val.clone();
+        val.as_object_mut().unwrap().insert(String::from("id"), Value::String(id.to_string()));
+        self.update(res_name, id, &val, if_match)
+    }
+
+    pub fn update(&self, res_name: &str, id: &str, val: &Value, if_match: Option<&str>) -> Result<RaResponse, RaError> {
+        let if_match = parse_etag(if_match)?;
+        self.check_version_aware(if_match)?;
+        self.schema.validate(&val)?;
+        let doc = bson::to_document(val)?;
+        let rd = self.get_res_def(&doc)?;
+
+        if res_name != rd.name {
+            return Err(RaError::bad_req(format!("received {}'s data on {}'s endpoint", &rd.name, res_name)));
+        }
+
+        let body_id = doc.get_str("id");
+        if let Err(e) = body_id {
+            return Err(RaError::bad_req("missing id in the resource"));
+        }
+        let body_id = body_id.unwrap();
+        if body_id != id {
+            return Err(RaError::bad_req(format!("id {} in the resource doesn't match with the id {} in the URL", body_id, id)));
+        }
+
+        let keep_history = self.config.versioning != Versioning::No_version;
+        let (doc, created) = self.db.update(rd, id, doc, &self.schema, keep_history, self.config.update_create, if_match)?;
+        if created {
+            return Ok(RaResponse::Created(doc));
+        }
+        Ok(RaResponse::Updated(doc))
+    }
+
+    /// applies the given patch on the current version of the resource
+    pub fn patch(&self, res_name: &str, id: &str, patch: &Value, if_match: Option<&str>) -> Result<RaResponse, RaError> {
+        debug!("patching {}/{}", res_name, id);
+        let mut if_match = parse_etag(if_match)?;
+        self.check_version_aware(if_match)?;
+        let rd = self.schema.get_res_def_by_name(res_name)?;
+        let current = self.db.read(rd, id)?;
+        if let None = if_match {
+            // the patched version must still be the current version at the time of saving
+            if_match = Some(bson_utils::get_int(&current, "meta.versionId") as u32);
+        }
+
+        let doc = self.apply_patch(rd, current, patch)?;
+        let keep_history = self.config.versioning != Versioning::No_version;
+        let (doc, _) = self.db.update(rd, id, doc, &self.schema, keep_history, false, if_match)?;
+        Ok(RaResponse::Updated(doc))
+    }
+
+    /// applies the patch on a copy of the given resource and validates the result
+    fn apply_patch(&self, rd: &ResourceDef, current: Document, patch: &Value) -> Result<Document, RaError> {
+        let id = current.get_str("id")?.to_string();
+        let val = serde_json::to_value(&current);
+        if let Err(e) = val {
+            return Err(RaError::DbError(format!("unable to convert {}/{} to JSON ({})", &rd.name, &id, e)));
+        }
+        let mut val = val.unwrap();
+
+        if patch.is_array() {
+            patch::apply_json_patch(&mut val, patch)?;
+        }
+        else if patch.get("resourceType").and_then(|r| r.as_str()) == Some("Parameters") {
+            patch::apply_fhirpath_patch(&mut val, patch, &self.schema)?;
+        }
+        else {
+            return Err(RaError::bad_req("unsupported patch format, expected either a JSON Patch or a Parameters resource"));
+        }
+
+        self.schema.validate(&val)?;
+        let doc = bson::to_document(&val)?;
+        if bson_utils::get_str(&doc, "resourceType") != rd.name || bson_utils::get_str(&doc, "id") != id {
+            return Err(RaError::bad_req("patch must not change the resourceType or id of the resource"));
+        }
+
+        Ok(doc)
+    }
+
+    pub fn delete(&self, res_name: &str, id: &str, if_match: Option<&str>) -> Result<RaResponse, RaError> {
+        debug!("deleting {}/{}", res_name, id);
+        let if_match = parse_etag(if_match)?;
+        let rd = self.schema.get_res_def_by_name(res_name)?;
+        let keep_history = self.config.versioning != Versioning::No_version;
+        self.db.delete(rd, id, &self.schema, keep_history, if_match)?;
+        Ok(RaResponse::Deleted)
+    }
+
+    /// deletes the resource matching the given search parameters
+    pub fn conditional_delete(&self, res_name: &str, query: &SearchQuery, if_match: Option<&str>) -> Result<RaResponse, RaError> {
+        if !self.config.conditional_delete {
+            return Err(RaError::bad_req("conditional delete is not supported"));
+        }
+
+        let rd = self.schema.get_res_def_by_name(res_name)?;
+        let matched = self.find_single_match(rd, query.params.clone())?;
+        if let None = matched {
+            return Err(RaError::not_found(format!("no {} resource matches the given conditions", res_name)));
+        }
+
+        let matched = matched.unwrap();
+        self.delete(res_name, matched.get_str("id")?, if_match)
+    }
+
+    pub fn read(&self, res_name: &str, id: &str) -> Result<RaResponse, RaError> {
+        debug!("reading {}/{}", res_name, id);
+        let rd = self.schema.get_res_def_by_name(res_name)?;
+        let doc = self.db.read(rd, id)?;
+        Ok(RaResponse::Resource(doc))
+    }
+
+    pub fn vread(&self, res_name: &str, id: &str, vid: &str) -> Result<RaResponse, RaError> {
+        debug!("reading version {} of {}/{}", vid, res_name, id);
+        let rd = self.schema.get_res_def_by_name(res_name)?;
+        let version = vid.parse::<u32>();
+        if let Err(e) = version {
+            return Err(RaError::not_found(format!("invalid version {} of {}/{}", vid, res_name, id)));
+        }
+        let doc = self.db.vread(rd, id, version.unwrap())?;
+        Ok(RaResponse::Resource(doc))
+    }
+
+    pub fn history_instance(&self, res_name: &str, id: &str, query: &HistoryQuery) -> Result<RaResponse, RaError> {
+        debug!("reading history of {}/{}", res_name, id);
+        let rd = self.schema.get_res_def_by_name(res_name)?;
+        let (since, at) = parse_history_params(query)?;
+        let entries = self.db.history_instance(rd, id, since, at, query.count as usize)?;
+        Ok(RaResponse::History(HistorySet::new(entries, &self.base_url)))
+    }
+
+    pub fn history_type(&self, res_name: &str, query: &HistoryQuery) -> Result<RaResponse, RaError> {
+        debug!("reading history of {}", res_name);
+        let rd = self.schema.get_res_def_by_name(res_name)?;
+        let (since, at) = parse_history_params(query)?;
+        let entries = self.db.history_type(rd, since, at, query.count as usize, &self.schema)?;
+        Ok(RaResponse::History(HistorySet::new(entries, &self.base_url)))
+    }
+
+    pub fn history_system(&self, query: &HistoryQuery) -> Result<RaResponse, RaError> {
+        debug!("reading history of all resources");
+        let (since, at) = parse_history_params(query)?;
+        let entries = self.db.history_system(since, at, query.count as usize, &self.schema)?;
+        Ok(RaResponse::History(HistorySet::new(entries, &self.base_url)))
+    }
+
+    /// converts the resource read using read or vread interactions into a NotModified response
+    /// if it matches the If-None-Match header or if it was not modified since the time given in If-Modified-Since
+    pub fn conditional_read(&self, resp: RaResponse, ch: &ConditionalHeaders) -> RaResponse {
+        if !self.config.conditional_read {
+            return resp;
+        }
+
+        if let RaResponse::Resource(doc) = resp {
+            let mut not_modified = false;
+            if let Some(if_none_match) = ch.if_none_match {
+                let vid = bson_utils::get_int(&doc, "meta.versionId");
+                for etag in if_none_match.split(",") {
+                    let etag = etag.trim();
+                    if etag == "*" {
+                        not_modified = true;
+                        break;
+                    }
+                    if let Ok(Some(v)) = parse_etag(Some(etag)) {
+                        if v as i64 == vid {
+                            not_modified = true;
+                            break;
+                        }
+                    }
+                }
+            }
+            // If-Modified-Since is ignored when If-None-Match is present
+            else if let Some(if_modified_since) = ch.if_modified_since {
+                let since = DateTime::parse_from_rfc2822(if_modified_since);
+                let last_updated = bson_utils::get_time(&doc, "meta.lastUpdated");
+                if let (Ok(since), Some(last_updated)) = (since, last_updated) {
+                    // HTTP dates do not have sub-second precision
+                    not_modified = last_updated.timestamp() <= since.timestamp();
+                }
+                else {
+                    debug!("ignoring the invalid If-Modified-Since header value {}", if_modified_since);
+                }
+            }
+
+            if not_modified {
+                return RaResponse::NotModified(doc);
+            }
+            return RaResponse::Resource(doc);
+        }
+
+        resp
+    }
+
     pub fn bundle(&self, val: Value) -> Result<RaResponse, RaError> {
+        self.bundle_with_hints(val, &ResponseHints::default())
+    }
+
+    pub fn bundle_with_hints(&self, val: Value, hints: &ResponseHints) -> Result<RaResponse, RaError> {
         let btype = val.get("type");
         if let None = btype {
             return Err(RaError::bad_req("missing type property"));
@@ -267,7 +856,8 @@ impl ApiBase {
         let btype = BundleType::from(btype.unwrap())?;
 
         match btype {
-            BundleType::Transaction => self.transaction(val),
+            BundleType::Transaction => self.transaction(val, hints),
+            BundleType::Batch => self.batch(val, hints),
             _ => {
                 return Err(RaError::bad_req(format!("unsupported bundle type {:?}", btype)));
             }
@@ -277,6 +867,50 @@ impl ApiBase {
     pub fn search_query(&self, res_name: &str, query: &SearchQuery, hints: &ResponseHints) -> Result<RaResponse, RaError> {
         debug!("searching on {}", res_name);
         let rd = self.schema.get_res_def_by_name(res_name)?;
+        let filter = self.build_filter(rd, query)?;
+        let resp = execute_search_query(&filter, query, rd, &self.db, &self.schema)?;
+        if let RaResponse::SearchResult(mut ss) = resp {
+            if !query.include.is_empty() || !query.revinclude.is_empty() {
+                let includes = self.parse_includes(query)?;
+                if !includes.is_empty() && !ss.entries.is_empty() {
+                    for doc in find_included(&ss, &includes, &self.schema, &self.db)? {
+                        ss.add_include(doc);
+                    }
+                }
+            }
+            let link = |page: Option<&str>| format!("{}/{}?{}", self.base_url.trim_end_matches('/'), res_name, query.to_query_string(page));
+            ss.links.push(("self", link(query.page)));
+            if let Some(next) = ss.next_page.as_deref() {
+                ss.links.push(("next", link(Some(next))));
+            }
+            if let Some(previous) = ss.prev_page.as_deref() {
+                ss.links.push(("previous", link(Some(previous))));
+            }
+            return Ok(RaResponse::SearchResult(ss));
+        }
+        Ok(resp)
+    }
+
+    /// parses the _include and _revinclude parameters, these are ignored when disabled in
+    /// the config and the client prefers lenient handling
+    fn parse_includes<'q>(&self, query: &SearchQuery<'q>) -> Result<Vec<IncludeParam<'q>>, RaError> {
+        let mut includes = Vec::new();
+        for (enabled, params) in [(self.config.search_include, &query.include), (self.config.search_rev_include, &query.revinclude)] {
+            for (name, value) in params {
+                if !enabled {
+                    if !query.ignore_unknown_params {
+                        return Err(RaError::bad_req(format!("{} is not supported", name)));
+                    }
+                    continue;
+                }
+                includes.push(parse_include(name, value)?);
+            }
+        }
+
+        Ok(includes)
+    }
+
+    fn build_filter<'r>(&self, rd: &ResourceDef, query: &SearchQuery) -> Result<Filter<'r>, RaError> {
         let mut filter= None;
         if query.params.len() == 1 {
             let (key, val) = query.params[0];
@@ -313,7 +947,68 @@ impl ApiBase {
             return Err(RaError::BadRequest(format!("none of the given search parameters are known to the server")));
         }
 
-        execute_search_query(&filter.unwrap(), query, rd, &self.db, &self.schema)
+        Ok(filter.unwrap())
+    }
+
+    /// searches for the resources matching the given query parameters, only the given maximum number of matches are fetched
+    fn find_matches(&self, rd: &ResourceDef, params: Vec<(&str, &str)>, max: u32) -> Result<SearchSet, RaError> {
+        if params.is_empty() {
+            return Err(RaError::bad_req("no search parameters were given for the conditional interaction"));
+        }
+
+        let mut sq = SearchQuery::new(params);
+        sq.count = max;
+        let filter = self.build_filter(rd, &sq)?;
+        let resp = execute_search_query(&filter, &sq, rd, &self.db, &self.schema)?;
+        match resp {
+            RaResponse::SearchResult(ss) => Ok(ss),
+            _ => Err(RaError::DbError(String::from("unexpected search result")))
+        }
+    }
+
+    /// finds the resources matching the conditions given in the `ifNoneExist` of the transaction's
+    /// entries, returns a map of fullUrls to the IDs of the matched resources
+    fn find_existing_for_conditional_creates(&self, val: &Value) -> Result<HashMap<String, String>, RaError> {
+        let mut existing = HashMap::new();
+        let entries = val.get("entry");
+        if let None = entries {
+            return Ok(existing);
+        }
+
+        if let Some(entries) = entries.unwrap().as_array() {
+            for item in entries {
+                let if_none_exist = item.pointer("/request/ifNoneExist");
+                if let None = if_none_exist {
+                    continue;
+                }
+                let if_none_exist = if_none_exist.unwrap().as_str().unwrap_or("");
+                let full_url = item.get("fullUrl").map_or("", |v| v.as_str().unwrap_or(""));
+                let res_name = item.pointer("/resource/resourceType").map_or("", |v| v.as_str().unwrap_or(""));
+                let rd = self.schema.get_res_def_by_name(res_name)?;
+                if !self.config.conditional_create {
+                    return Err(RaError::bad_req("conditional create is not supported"));
+                }
+                let params = parse_query_string(if_none_exist);
+                let params = params.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+                let matched = self.find_single_match(rd, params)?;
+                if let Some(doc) = matched {
+                    existing.insert(full_url.to_string(), doc.get_str("id")?.to_string());
+                }
+            }
+        }
+
+        Ok(existing)
+    }
+
+    /// returns the resource if only one matches the given query parameters, fails
+    /// with a 412 if more than one resource matches
+    fn find_single_match(&self, rd: &ResourceDef, params: Vec<(&str, &str)>) -> Result<Option<Document>, RaError> {
+        let mut ss = self.find_matches(rd, params, 2)?;
+        match ss.len() {
+            0 => Ok(None),
+            1 => Ok(Some(ss.entries.remove(0).resource)),
+            _ => Err(RaError::precondition_failed(IssueType::Multiple_matches, format!("multiple {} resources match the given conditions", &rd.name)))
+        }
     }
 
     pub fn search(&self, rd: &ResourceDef, filter: &Ast) -> Result<RaResponse, RaError> {
@@ -322,16 +1017,118 @@ impl ApiBase {
     }
 
     pub fn generate_capability_statement(&self) -> Result<RaResponse, RaError> {
-        let cs = gen_capability_stmt(&self.schema, &self.base_url);
+        let cs = gen_capability_stmt(&self.schema, &self.base_url, &self.config);
         Ok(RaResponse::Success(Some(cs)))
     }
 
+    /// in versioned-update mode the version being updated must be given in If-Match
+    fn check_version_aware(&self, if_match: Option<u32>) -> Result<(), RaError> {
+        if self.config.versioning == Versioning::Versioned_Update && if_match.is_none() {
+            return Err(RaError::precondition_failed(IssueType::Business_rule, "a version-aware update is required, the If-Match header is missing"));
+        }
+        Ok(())
+    }
+
     fn get_res_def(&self, d: &Document) -> Result<&ResourceDef, RaError>{
         let res_type = d.get_str("resourceType")?;
         self.schema.get_res_def_by_name(res_type)
     }
 }
 
+/// converts the result of processing an entry of a batch or a transaction into a response entry
+fn to_response_entry(resp: Result<RaResponse, RaError>, representation: bool) -> ResponseEntry {
+    match resp {
+        Ok(resp) => {
+            match resp {
+                RaResponse::Created(doc) => ResponseEntry::from_doc("201 Created", &doc, true, representation),
+                RaResponse::Updated(doc) => ResponseEntry::from_doc("200 OK", &doc, true, representation),
+                RaResponse::Deleted => ResponseEntry::new("204 No Content"),
+                RaResponse::Resource(doc) => ResponseEntry::from_doc("200 OK", &doc, false, true),
+                RaResponse::NotModified(doc) => ResponseEntry::from_doc("304 Not Modified", &doc, false, false),
+                RaResponse::Success(doc) => {
+                    let mut entry = ResponseEntry::new("200 OK");
+                    entry.resource = doc.and_then(|d| serde_json::to_value(d).ok());
+                    entry
+                },
+                RaResponse::SearchResult(ss) => {
+                    let mut entry = ResponseEntry::new("200 OK");
+                    entry.resource = serde_json::to_value(ss).ok();
+                    entry
+                },
+                RaResponse::History(hs) => {
+                    let mut entry = ResponseEntry::new("200 OK");
+                    entry.resource = serde_json::to_value(hs).ok();
+                    entry
+                },
+                RaResponse::Bundle(rb) => {
+                    let mut entry = ResponseEntry::new("200 OK");
+                    entry.resource = serde_json::to_value(rb).ok();
+                    entry
+                }
+            }
+        },
+        Err(e) => {
+            let (code, outcome) = e.to_outcome();
+            let status = Status::from_code(code).map_or(code.to_string(), |s| s.to_string());
+            ResponseEntry::from_error(status, outcome)
+        }
+    }
+}
+
+/// parses the version number from the given ETag value, accepts W/"n", "n" and n formats
+fn parse_etag(etag: Option<&str>) -> Result<Option<u32>, RaError> {
+    if let None = etag {
+        return Ok(None);
+    }
+
+    let etag = etag.unwrap();
+    let version = etag.trim_start_matches("W/").trim_matches('"').parse::<u32>();
+    if let Err(e) = version {
+        return Err(RaError::bad_req(format!("invalid ETag {}", etag)));
+    }
+
+    Ok(Some(version.unwrap()))
+}
+
+/// adds the serialized resource to the staged resources of a transaction
+fn stage(staged: &mut StagedResources, rd: &ResourceDef, ksid: &Ksuid, doc: &Document) -> Result<(), RaError> {
+    let mut vec_bytes = Vec::new();
+    doc.to_writer(&mut vec_bytes)?;
+    staged.insert(rd.new_id(ksid.as_bytes()), vec_bytes);
+    Ok(())
+}
+
+/// parses the query string of a conditional interaction (e.g identifier=http://acme.org|123),
+/// the resource type is optional in the query
+fn parse_query_string(query: &str) -> Vec<(String, String)> {
+    let query = match query.find('?') {
+        Some(pos) => &query[pos + 1..],
+        None => query
+    };
+
+    url::form_urlencoded::parse(query.as_bytes()).into_owned().collect()
+}
+
+fn parse_history_params(query: &HistoryQuery) -> Result<(Option<DateTime<Utc>>, Option<DateTime<Utc>>), RaError> {
+    let mut since = None;
+    if let Some(s) = query.since {
+        since = utils::parse_instant(s);
+        if let None = since {
+            return Err(RaError::bad_req(format!("invalid value {} given for _since parameter", s)));
+        }
+    }
+
+    let mut at = None;
+    if let Some(s) = query.at {
+        at = utils::parse_instant(s);
+        if let None = at {
+            return Err(RaError::bad_req(format!("invalid value {} given for _at parameter", s)));
+        }
+    }
+
+    Ok((since, at))
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
@@ -341,7 +1138,12 @@ mod tests {
     use serde_json::json;
     use crate::configure_log4rs;
 
-    use crate::utils::test_utils::parse_expression;
+    use crate::api::bundle::SearchEntryMode;
+    use crate::barn::is_tombstone;
+    use crate::search::executor::to_index_scanner;
+    use crate::search::parse_filter;
+    use crate::utils::bson_utils;
+    use crate::utils::test_utils::{create_resource, parse_expression, read_patient, read_patient_example, search_entries, TestContainer, to_reference, update};
 
     use super::*;
 
@@ -356,8 +1158,24 @@ mod tests {
 
         let f = File::open("test_data/resources/bundle-example.json").unwrap();
         let val: Value = serde_json::from_reader(f).unwrap();
+        let res_types: Vec<String> = val.get("entry").unwrap().as_array().unwrap().iter()
+            .map(|e| e.pointer("/resource/resourceType").unwrap().as_str().unwrap().to_string()).collect();
 
         let resp = gateway.bundle(val)?;
+        if let RaResponse::Bundle(rb) = resp {
+            assert_eq!(BundleType::TransactionResponse, rb.btype);
+            // responses must be in the same order as the entries of the request
+            assert_eq!(res_types.len(), rb.len());
+            for (rt, entry) in res_types.iter().zip(rb.entries.iter()) {
+                assert_eq!("201 Created", entry.status);
+                assert!(entry.location.as_ref().unwrap().starts_with(&format!("{}/", rt)));
+                assert_eq!(Some(String::from("W/\"1\"")), entry.etag);
+                assert!(entry.resource.is_none());
+            }
+        }
+        else {
+            assert!(false, "expected a transaction-response bundle");
+        }
         let patient_schema = gateway.schema.resources.get("Practitioner").unwrap();
         let filter = parse_expression("name.where(family = 'Kuvalis369')");
         let results = gateway.search(patient_schema, &filter)?;
@@ -372,6 +1190,967 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_read_and_vread() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let data = read_patient();
+        let created = api_base.create("Patient", &data)?;
+        let id;
+        if let RaResponse::Created(doc) = created {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+
+        let resp = api_base.read("Patient", &id)?;
+        if let RaResponse::Resource(doc) = resp {
+            assert_eq!(id, doc.get_str("id")?);
+        }
+        else {
+            assert!(false, "expected a resource");
+        }
+
+        let resp = api_base.vread("Patient", &id, "1")?;
+        assert!(matches!(resp, RaResponse::Resource(_)));
+
+        let resp = api_base.vread("Patient", &id, "2");
+        assert!(matches!(resp, Err(RaError::NotFound(_))));
+
+        let resp = api_base.vread("Patient", &id, "abc");
+        assert!(matches!(resp, Err(RaError::NotFound(_))));
+
+        let unknown_id = ksuid::Ksuid::generate().to_base62();
+        let resp = api_base.read("Patient", &unknown_id);
+        assert!(matches!(resp, Err(RaError::NotFound(_))));
+
+        let resp = api_base.read("Patient", "not-a-valid-id");
+        assert!(matches!(resp, Err(RaError::NotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_versioned_update() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let mut api_base = tc.setup_api_base_with_example_patient();
+        api_base.config.versioning = Versioning::Versioned_Update;
+        let mut data = read_patient();
+        let id;
+        if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+        data.as_object_mut().unwrap().insert(String::from("id"), Value::String(id.clone()));
+
+        let resp = api_base.update("Patient", &id, &data, None);
+        assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+
+        let resp = api_base.update("Patient", &id, &data, Some("W/\"2\""));
+        assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+
+        let resp = api_base.update("Patient", &id, &data, Some("W/\"1\""))?;
+        assert!(matches!(resp, RaResponse::Updated(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_update() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let mut api_base = tc.setup_api_base_with_example_patient();
+        let mut data = read_patient();
+        let id;
+        if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+
+        let rd = api_base.schema.get_res_def_by_name("Patient")?;
+        let filter = parse_filter("family eq \"Updated-Family-Name\"")?;
+        let keys = to_index_scanner(&filter, rd, &api_base.schema, &api_base.db)?.collect_all();
+        assert_eq!(0, keys.len());
+
+        let old_family = data.pointer("/name/0/family").unwrap().as_str().unwrap().to_string();
+        data.as_object_mut().unwrap().insert(String::from("id"), Value::String(id.clone()));
+        update(&mut data, "/name/0/family", Value::String(String::from("Updated-Family-Name")));
+        let resp = api_base.update("Patient", &id, &data, None)?;
+        if let RaResponse::Updated(doc) = resp {
+            assert_eq!("2", doc.get_document("meta")?.get_str("versionId")?);
+        }
+        else {
+            assert!(false, "expected an updated resource");
+        }
+        let keys = to_index_scanner(&filter, rd, &api_base.schema, &api_base.db)?.collect_all();
+        assert_eq!(1, keys.len());
+        // the index rows of the previous value must be removed
+        let pk = rd.new_id(ksuid::Ksuid::from_base62(&id)?.as_bytes());
+        let filter = parse_filter(&format!("family eq \"{}\"", old_family))?;
+        let keys = to_index_scanner(&filter, rd, &api_base.schema, &api_base.db)?.collect_all();
+        assert!(!keys.contains_key(&pk));
+
+        let resp = api_base.read("Patient", &id)?;
+        if let RaResponse::Resource(doc) = resp {
+            assert_eq!("Updated-Family-Name", bson_utils::get_str(doc.get_array("name")?[0].as_document().unwrap(), "family"));
+        }
+
+        // the first version must be available in history
+        let resp = api_base.vread("Patient", &id, "1")?;
+        if let RaResponse::Resource(doc) = resp {
+            assert_ne!("Updated-Family-Name", bson_utils::get_str(doc.get_array("name")?[0].as_document().unwrap(), "family"));
+        }
+
+        // mismatched IDs
+        let resp = api_base.update("Patient", "another-id", &data, None);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+
+        // update-create is disabled by default
+        data.as_object_mut().unwrap().insert(String::from("id"), Value::String(String::from("example-1.a")));
+        let resp = api_base.update("Patient", "example-1.a", &data, None);
+        assert!(matches!(resp, Err(RaError::NotFound(_))));
+
+        // the ID assigned by the client is retained
+        api_base.config.update_create = true;
+        let resp = api_base.update("Patient", "example-1.a", &data, None)?;
+        if let RaResponse::Created(doc) = resp {
+            assert_eq!("example-1.a", doc.get_str("id")?);
+            assert_eq!("1", doc.get_document("meta")?.get_str("versionId")?);
+        }
+        else {
+            panic!("expected a created resource");
+        }
+        let resp = api_base.update("Patient", "example-1.a", &data, None)?;
+        assert!(matches!(resp, RaResponse::Updated(_)));
+        let resp = api_base.read("Patient", "example-1.a")?;
+        if let RaResponse::Resource(doc) = resp {
+            assert_eq!("example-1.a", doc.get_str("id")?);
+            assert_eq!("2", doc.get_document("meta")?.get_str("versionId")?);
+        }
+        else {
+            panic!("expected the resource");
+        }
+        let resp = api_base.read("Patient", "invalid_id!");
+        assert!(matches!(resp, Err(RaError::NotFound(_))));
+
+        api_base.config.update_create = false;
+        let unknown_id = ksuid::Ksuid::generate().to_base62();
+        data.as_object_mut().unwrap().insert(String::from("id"), Value::String(unknown_id.clone()));
+        let resp = api_base.update("Patient", &unknown_id, &data, None);
+        assert!(matches!(resp, Err(RaError::NotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let data = read_patient();
+        let id;
+        if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+
+        let rd = api_base.schema.get_res_def_by_name("Patient")?;
+        let pk = rd.new_id(ksuid::Ksuid::from_base62(&id)?.as_bytes());
+        let family = data.pointer("/name/0/family").unwrap().as_str().unwrap().to_string();
+        let filter = parse_filter(&format!("family eq \"{}\"", family))?;
+        let keys = to_index_scanner(&filter, rd, &api_base.schema, &api_base.db)?.collect_all();
+        assert!(keys.contains_key(&pk));
+
+        let resp = api_base.delete("Patient", &id, None)?;
+        assert!(matches!(resp, RaResponse::Deleted));
+
+        let keys = to_index_scanner(&filter, rd, &api_base.schema, &api_base.db)?.collect_all();
+        assert!(!keys.contains_key(&pk));
+
+        let resp = api_base.read("Patient", &id);
+        assert!(matches!(resp, Err(RaError::Custom{code: 410, ..})));
+        let resp = api_base.vread("Patient", &id, "2");
+        assert!(matches!(resp, Err(RaError::Custom{code: 410, ..})));
+        let resp = api_base.vread("Patient", &id, "1")?;
+        assert!(matches!(resp, RaResponse::Resource(_)));
+
+        // deleting again is not an error
+        let resp = api_base.delete("Patient", &id, None)?;
+        assert!(matches!(resp, RaResponse::Deleted));
+
+        let unknown_id = ksuid::Ksuid::generate().to_base62();
+        let resp = api_base.delete("Patient", &unknown_id, None);
+        assert!(matches!(resp, Err(RaError::NotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_history() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let mut data = read_patient();
+        let id;
+        if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+
+        data.as_object_mut().unwrap().insert(String::from("id"), Value::String(id.clone()));
+        update(&mut data, "/gender", Value::String(String::from("female")));
+        api_base.update("Patient", &id, &data, None)?;
+        api_base.delete("Patient", &id, None)?;
+
+        let query = HistoryQuery{count: 100, since: None, at: None};
+        let all_versions = get_history_docs(api_base.history_instance("Patient", &id, &query)?);
+        let versions: Vec<String> = all_versions.iter().map(get_version_id).collect();
+        assert_eq!(vec!["3", "2", "1"], versions);
+
+        let query = HistoryQuery{count: 1, since: None, at: None};
+        let versions = get_history_versions(api_base.history_instance("Patient", &id, &query)?);
+        assert_eq!(vec!["3"], versions);
+
+        // the expected versions are derived from the stored lastUpdated times,
+        // the changes may have been made within the same millisecond
+        let created_at = bson_utils::get_str(&all_versions[2], "meta.lastUpdated").to_string();
+        let updated_at = bson_utils::get_str(&all_versions[1], "meta.lastUpdated").to_string();
+
+        let query = HistoryQuery{count: 100, since: None, at: Some(&created_at)};
+        let versions = get_history_versions(api_base.history_instance("Patient", &id, &query)?);
+        let expected: Vec<String> = all_versions.iter().find(|d| bson_utils::get_str(d, "meta.lastUpdated") <= created_at.as_str())
+            .filter(|d| !is_tombstone(d)).map(get_version_id).into_iter().collect();
+        assert_eq!(expected, versions);
+
+        let query = HistoryQuery{count: 100, since: None, at: None};
+        let all_changes = get_history_docs(api_base.history_type("Patient", &query)?);
+        let expected: Vec<String> = all_changes.iter().filter(|d| bson_utils::get_str(d, "meta.lastUpdated") >= updated_at.as_str()).map(get_version_id).collect();
+        assert!(expected.starts_with(&[String::from("3"), String::from("2")]));
+
+        let query = HistoryQuery{count: 100, since: Some(&updated_at), at: None};
+        let versions = get_history_versions(api_base.history_type("Patient", &query)?);
+        assert_eq!(expected, versions);
+        let versions = get_history_versions(api_base.history_system(&query)?);
+        assert_eq!(expected, versions);
+
+        let query = HistoryQuery{count: 100, since: None, at: None};
+        let versions = get_history_versions(api_base.history_type("Patient", &query)?);
+        assert_eq!(4, versions.len());
+
+        let query = HistoryQuery{count: 100, since: Some("not-a-date"), at: None};
+        let resp = api_base.history_system(&query);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        Ok(())
+    }
+
+    fn get_history_versions(resp: RaResponse) -> Vec<String> {
+        get_history_docs(resp).iter().map(get_version_id).collect()
+    }
+
+    fn get_history_docs(resp: RaResponse) -> Vec<Document> {
+        if let RaResponse::History(hs) = resp {
+            return hs.entries;
+        }
+        panic!("expected a history bundle");
+    }
+
+    fn get_version_id(doc: &Document) -> String {
+        bson_utils::get_str(doc, "meta.versionId").to_string()
+    }
+
+    #[test]
+    fn test_conditional_create() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let mut api_base = tc.setup_api_base_with_example_patient();
+        let data = read_patient_example();
+
+        let resp = api_base.create_if_none_exist("Patient", &data, "family=Chalmers");
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+
+        api_base.config.conditional_create = true;
+
+        let resp = api_base.create_if_none_exist("Patient", &data, "family=Chalmers")?;
+        assert!(matches!(resp, RaResponse::Resource(_)));
+
+        // all the conditions must match
+        let resp = api_base.create_if_none_exist("Patient", &data, "family=Chalmers&gender=female")?;
+        assert!(matches!(resp, RaResponse::Created(_)));
+
+        let resp = api_base.create_if_none_exist("Patient", &data, "Patient?family=Chalmers");
+        assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+
+        let resp = api_base.create_if_none_exist("Patient", &data, "unknown-param=1");
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditional_update_and_delete() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let mut api_base = tc.setup_api_base_with_example_patient();
+        let mut data = read_patient_example();
+        data.as_object_mut().unwrap().remove("id");
+
+        let query = SearchQuery::new(vec![("family", "Chalmers")]);
+        let resp = api_base.conditional_update("Patient", &query, &data, None);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        let resp = api_base.conditional_delete("Patient", &query, None);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+
+        api_base.config.conditional_update = true;
+        api_base.config.conditional_delete = true;
+        let resp = api_base.conditional_update("Patient", &query, &data, None)?;
+        let id;
+        if let RaResponse::Updated(doc) = resp {
+            assert_eq!("2", doc.get_document("meta")?.get_str("versionId")?);
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected an updated resource");
+        }
+
+        // the id in the resource must match with the id of the matched resource
+        data.as_object_mut().unwrap().insert(String::from("id"), Value::String(ksuid::Ksuid::generate().to_base62()));
+        let resp = api_base.conditional_update("Patient", &query, &data, None);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        data.as_object_mut().unwrap().remove("id");
+
+        let resp = api_base.conditional_delete("Patient", &query, None)?;
+        assert!(matches!(resp, RaResponse::Deleted));
+        let resp = api_base.read("Patient", &id);
+        assert!(matches!(resp, Err(RaError::Custom{code: 410, ..})));
+        let resp = api_base.conditional_delete("Patient", &query, None);
+        assert!(matches!(resp, Err(RaError::NotFound(_))));
+
+        let no_match_query = SearchQuery::new(vec![("family", "Nobody")]);
+        let resp = api_base.conditional_update("Patient", &no_match_query, &data, None)?;
+        assert!(matches!(resp, RaResponse::Created(_)));
+        api_base.create("Patient", &data)?;
+
+        let resp = api_base.conditional_update("Patient", &query, &data, None);
+        assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+        let resp = api_base.conditional_delete("Patient", &query, None);
+        assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+        Ok(())
+    }
+
+    #[test]
+    fn test_if_match() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let mut data = read_patient();
+        let id;
+        if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+
+        data.as_object_mut().unwrap().insert(String::from("id"), Value::String(id.clone()));
+        let resp = api_base.update("Patient", &id, &data, Some("W/\"1\""))?;
+        assert!(matches!(resp, RaResponse::Updated(_)));
+
+        // a stale version
+        let resp = api_base.update("Patient", &id, &data, Some("W/\"1\""));
+        assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+        let resp = api_base.delete("Patient", &id, Some("1"));
+        assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+
+        let resp = api_base.update("Patient", &id, &data, Some("W/2"));
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+
+        let resp = api_base.delete("Patient", &id, Some("\"2\""))?;
+        assert!(matches!(resp, RaResponse::Deleted));
+        Ok(())
+    }
+
+    #[test]
+    fn test_versioning_modes() -> Result<(), Error> {
+        for versioning in [Versioning::No_version, Versioning::Versioned, Versioning::Versioned_Update] {
+            let tc = TestContainer::new();
+            let mut api_base = tc.setup_api_base_with_example_patient();
+            api_base.config.versioning = versioning;
+            let mut data = read_patient();
+            let id;
+            if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+                id = doc.get_str("id")?.to_string();
+            }
+            else {
+                panic!("expected the created resource");
+            }
+            data.as_object_mut().unwrap().insert(String::from("id"), Value::String(id.clone()));
+
+            let resp = api_base.update("Patient", &id, &data, None);
+            let patch = json!([{"op": "replace", "path": "/gender", "value": "female"}]);
+            if versioning == Versioning::Versioned_Update {
+                assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+                let resp = api_base.patch("Patient", &id, &patch, None);
+                assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+                let resp = api_base.update("Patient", &id, &data, Some("W/\"1\""))?;
+                assert!(matches!(resp, RaResponse::Updated(_)));
+                let resp = api_base.patch("Patient", &id, &patch, Some("W/\"2\""))?;
+                assert!(matches!(resp, RaResponse::Updated(_)));
+            }
+            else {
+                assert!(matches!(resp, Ok(RaResponse::Updated(_))));
+                let resp = api_base.patch("Patient", &id, &patch, None)?;
+                assert!(matches!(resp, RaResponse::Updated(_)));
+            }
+
+            // the older versions are kept only when versioning is enabled
+            assert_eq!(versioning != Versioning::No_version, api_base.vread("Patient", &id, "1").is_ok(), "{:?}", versioning);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditional_read() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let mut api_base = tc.setup_api_base_with_example_patient();
+        let data = read_patient();
+        let id;
+        if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+
+        let mut ch = ConditionalHeaders{if_none_exist: None, if_match: None, if_none_match: Some("W/\"1\""), if_modified_since: None};
+        let resp = api_base.conditional_read(api_base.read("Patient", &id)?, &ch);
+        assert!(matches!(resp, RaResponse::Resource(_)));
+
+        api_base.config.conditional_read = true;
+        let resp = api_base.conditional_read(api_base.read("Patient", &id)?, &ch);
+        assert!(matches!(resp, RaResponse::NotModified(_)));
+
+        ch.if_none_match = Some("W/\"3\", W/\"2\"");
+        let resp = api_base.conditional_read(api_base.read("Patient", &id)?, &ch);
+        assert!(matches!(resp, RaResponse::Resource(_)));
+
+        ch.if_none_match = None;
+        ch.if_modified_since = Some("Sun, 06 Nov 1994 08:49:37 GMT");
+        let resp = api_base.conditional_read(api_base.vread("Patient", &id, "1")?, &ch);
+        assert!(matches!(resp, RaResponse::Resource(_)));
+
+        let future = (Utc::now() + chrono::Duration::days(1)).to_rfc2822();
+        ch.if_modified_since = Some(&future);
+        let resp = api_base.conditional_read(api_base.vread("Patient", &id, "1")?, &ch);
+        assert!(matches!(resp, RaResponse::NotModified(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_patch() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let data = read_patient();
+        let id;
+        if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+
+        let patch = json!([{"op": "replace", "path": "/gender", "value": "female"}]);
+        let resp = api_base.patch("Patient", &id, &patch, None)?;
+        if let RaResponse::Updated(doc) = resp {
+            assert_eq!("2", doc.get_document("meta")?.get_str("versionId")?);
+            assert_eq!("female", doc.get_str("gender")?);
+        }
+        else {
+            panic!("expected an updated resource");
+        }
+
+        let resp = api_base.patch("Patient", &id, &patch, Some("W/\"1\""));
+        assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+
+        let patch = json!([{"op": "replace", "path": "/id", "value": "another-id"}]);
+        let resp = api_base.patch("Patient", &id, &patch, None);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+
+        // a JSON Patch wrapped in a Binary resource of a transaction
+        let patch = json!([{"op": "replace", "path": "/gender", "value": "male"}]);
+        let bundle = json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": [{
+                "resource": {"resourceType": "Binary", "contentType": "application/json-patch+json", "data": base64::encode(patch.to_string())},
+                "request": {"method": "PATCH", "url": format!("Patient/{}", &id)}
+            }]
+        });
+        api_base.bundle(bundle)?;
+        if let RaResponse::Resource(doc) = api_base.read("Patient", &id)? {
+            assert_eq!("3", doc.get_document("meta")?.get_str("versionId")?);
+            assert_eq!("male", doc.get_str("gender")?);
+        }
+        else {
+            panic!("expected a resource");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fhirpath_patch() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let data = read_patient();
+        let id;
+        if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+
+        let patch = json!({
+            "resourceType": "Parameters",
+            "parameter": [{"name": "operation", "part": [{"name": "type", "valueCode": "replace"}, {"name": "path", "valueString": "Patient.gender"}, {"name": "value", "valueCode": "female"}]}]
+        });
+        let resp = api_base.patch("Patient", &id, &patch, None)?;
+        if let RaResponse::Updated(doc) = resp {
+            assert_eq!("2", doc.get_document("meta")?.get_str("versionId")?);
+            assert_eq!("female", doc.get_str("gender")?);
+        }
+        else {
+            panic!("expected an updated resource");
+        }
+
+        // the patched resource must be valid
+        let patch = json!({
+            "resourceType": "Parameters",
+            "parameter": [{"name": "operation", "part": [{"name": "type", "valueCode": "replace"}, {"name": "path", "valueString": "Patient.gender"}, {"name": "value", "valueInteger": 1}]}]
+        });
+        let resp = api_base.patch("Patient", &id, &patch, None);
+        assert!(matches!(resp, Err(RaError::SchemaValidationError)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let data = read_patient();
+        let id;
+        if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+
+        let patch = json!([{"op": "replace", "path": "/gender", "value": "female"}]);
+        let unknown_id = ksuid::Ksuid::generate().to_base62();
+        let bundle = json!({
+            "resourceType": "Bundle",
+            "type": "batch",
+            "entry": [
+                {"resource": data, "request": {"method": "POST", "url": "Patient"}},
+                {"request": {"method": "GET", "url": format!("Patient/{}", &id)}},
+                {"request": {"method": "DELETE", "url": format!("Patient/{}", &unknown_id)}},
+                {
+                    "resource": {"resourceType": "Binary", "contentType": "application/json-patch+json", "data": base64::encode(patch.to_string())},
+                    "request": {"method": "PATCH", "url": format!("Patient/{}", &id)}
+                },
+                {"request": {"method": "HEAD", "url": format!("Patient/{}/_history/1", &id)}}
+            ]
+        });
+
+        let resp = api_base.bundle(bundle)?;
+        let rb;
+        if let RaResponse::Bundle(b) = resp {
+            rb = b;
+        }
+        else {
+            panic!("expected a batch-response bundle");
+        }
+        assert_eq!(BundleType::BatchResponse, rb.btype);
+        assert_eq!(5, rb.len());
+        assert_eq!("201 Created", rb.entries[0].status);
+        assert!(rb.entries[0].location.is_some());
+        assert_eq!("200 OK", rb.entries[1].status);
+        assert!(rb.entries[1].resource.is_some());
+        // the failed entry doesn't affect the others
+        assert!(rb.entries[2].status.starts_with("404"));
+        assert!(rb.entries[2].outcome.is_some());
+        assert_eq!("200 OK", rb.entries[3].status);
+        assert_eq!(Some(String::from("W/\"2\"")), rb.entries[3].etag);
+        assert_eq!("200 OK", rb.entries[4].status);
+        assert!(rb.entries[4].resource.is_none());
+
+        let val = serde_json::to_value(&rb)?;
+        assert_eq!("batch-response", val.get("type").unwrap().as_str().unwrap());
+        assert_eq!("404 Not Found", val.pointer("/entry/2/response/status").unwrap().as_str().unwrap());
+        assert_eq!("OperationOutcome", val.pointer("/entry/2/response/outcome/resourceType").unwrap().as_str().unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_with_conditional_refs() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let mut api_base = tc.setup_api_base_with_example_patient();
+        let mut data = read_patient();
+        let id;
+        if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+            id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+        let rd = api_base.schema.get_res_def_by_name("Patient")?;
+        let chalmers = api_base.find_single_match(rd, vec![("family", "Chalmers")])?.unwrap();
+        let chalmers_id = chalmers.get_str("id")?;
+
+        data.as_object_mut().unwrap().insert(String::from("id"), Value::String(id.clone()));
+        data.as_object_mut().unwrap().insert(String::from("gender"), Value::String(String::from("female")));
+        let mut new_patient = read_patient();
+        new_patient.as_object_mut().unwrap().remove("id");
+        let bundle = json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": [
+                {"request": {"method": "GET", "url": format!("Patient/{}", &id)}},
+                {"resource": data.clone(), "request": {"method": "PUT", "url": format!("Patient/{}", &id), "ifMatch": "W/\"1\""}},
+                {
+                    "fullUrl": "urn:uuid:88f151c0-a954-468a-88bd-5ae15c08e059",
+                    "resource": {"resourceType": "Observation", "status": "final", "code": {"text": "weight"}, "subject": {"reference": "Patient?family=Chalmers"}},
+                    "request": {"method": "POST", "url": "Observation"}
+                },
+                {"request": {"method": "DELETE", "url": "Patient?family=Chalmers"}},
+                {"resource": new_patient, "request": {"method": "PUT", "url": "Patient?family=Nobody"}}
+            ]
+        });
+
+        // conditional updates and deletes are rejected unless they are enabled
+        let resp = api_base.bundle(bundle.clone());
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        api_base.config.conditional_update = true;
+        let resp = api_base.bundle(bundle.clone());
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        api_base.config.conditional_delete = true;
+
+        let resp = api_base.bundle(bundle)?;
+        let rb;
+        if let RaResponse::Bundle(b) = resp {
+            rb = b;
+        }
+        else {
+            panic!("expected a transaction-response bundle");
+        }
+        assert_eq!(5, rb.len());
+        // reads are processed after all the writes
+        assert_eq!("200 OK", rb.entries[0].status);
+        assert_eq!("female", rb.entries[0].resource.as_ref().unwrap().get("gender").unwrap().as_str().unwrap());
+        assert_eq!("200 OK", rb.entries[1].status);
+        assert_eq!(Some(String::from("W/\"2\"")), rb.entries[1].etag);
+        assert_eq!("201 Created", rb.entries[2].status);
+        assert_eq!("204 No Content", rb.entries[3].status);
+        // a conditional update without any matches creates a new resource
+        assert_eq!("201 Created", rb.entries[4].status);
+
+        let location = rb.entries[2].location.as_ref().unwrap();
+        let obs_id = location.split('/').nth(1).unwrap();
+        if let RaResponse::Resource(obs) = api_base.read("Observation", obs_id)? {
+            assert_eq!(format!("Patient/{}", chalmers_id), obs.get_document("subject")?.get_str("reference")?);
+        }
+        else {
+            panic!("expected the Observation resource");
+        }
+        let resp = api_base.read("Patient", chalmers_id);
+        assert!(matches!(resp, Err(RaError::Custom{code: 410, ..})));
+
+        // unresolvable conditional references fail the whole transaction
+        let bundle = json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": [
+                {
+                    "resource": {"resourceType": "Observation", "status": "final", "code": {"text": "weight"}, "subject": {"reference": "Patient?family=Chalmers"}},
+                    "request": {"method": "POST", "url": "Observation"}
+                }
+            ]
+        });
+        let resp = api_base.bundle(bundle);
+        assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+
+        // references other than Type?query are not conditional even if they have a query
+        for reference in ["http://other.org/fhir/Patient?identifier=x", "Unknown?identifier=x"] {
+            let bundle = json!({
+                "resourceType": "Bundle",
+                "type": "transaction",
+                "entry": [
+                    {
+                        "resource": {"resourceType": "Observation", "status": "final", "code": {"text": "weight"}, "subject": {"reference": reference}},
+                        "request": {"method": "POST", "url": "Observation"}
+                    }
+                ]
+            });
+            if let RaResponse::Bundle(rb) = api_base.bundle(bundle)? {
+                assert_eq!("201 Created", rb.entries[0].status);
+                let location = rb.entries[0].location.as_ref().unwrap();
+                let obs_id = location.split('/').nth(1).unwrap();
+                if let RaResponse::Resource(obs) = api_base.read("Observation", obs_id)? {
+                    assert_eq!(reference, obs.get_document("subject")?.get_str("reference")?);
+                }
+                else {
+                    panic!("expected the Observation resource");
+                }
+            }
+            else {
+                panic!("expected a transaction-response bundle");
+            }
+        }
+
+        // the same resource can't be the target of more than one entry
+        let bundle = json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": [
+                {"request": {"method": "DELETE", "url": format!("Patient/{}", &id)}},
+                {"resource": data, "request": {"method": "PUT", "url": format!("Patient/{}", &id)}}
+            ]
+        });
+        let resp = api_base.bundle(bundle);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_is_atomic() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let obs = json!({"resourceType": "Observation", "status": "final", "code": {"text": "weight"}});
+        let obs_id;
+        if let RaResponse::Created(doc) = api_base.create("Observation", &obs)? {
+            obs_id = doc.get_str("id")?.to_string();
+        }
+        else {
+            panic!("expected the created resource");
+        }
+
+        let mut patient = read_patient();
+        patient.as_object_mut().unwrap().remove("id");
+        patient.pointer_mut("/name/0").unwrap().as_object_mut().unwrap().insert(String::from("family"), Value::String(String::from("Atomic")));
+        // the updated Observation refers to the Patient created in the same transaction, the index rows of both
+        // must be generated before anything is saved
+        let bundle = json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": [
+                {
+                    "resource": {"resourceType": "Observation", "id": &obs_id, "status": "final", "code": {"text": "weight"}, "subject": {"reference": "urn:uuid:5d2e2f4c-3b2c-4c1f-8d3a-2f1e6a6b9c01"}},
+                    "request": {"method": "PUT", "url": format!("Observation/{}", &obs_id)}
+                },
+                {"fullUrl": "urn:uuid:5d2e2f4c-3b2c-4c1f-8d3a-2f1e6a6b9c01", "resource": patient.clone(), "request": {"method": "POST", "url": "Patient"}}
+            ]
+        });
+        api_base.bundle(bundle)?;
+        let rd = api_base.schema.get_res_def_by_name("Patient")?;
+        let created = api_base.find_single_match(rd, vec![("family", "Atomic")])?;
+        assert!(created.is_some());
+
+        // nothing gets saved when one of the entries fails
+        patient.pointer_mut("/name/0").unwrap().as_object_mut().unwrap().insert(String::from("family"), Value::String(String::from("Rollback")));
+        let bundle = json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": [
+                {"fullUrl": "urn:uuid:0c8a7d6e-1f2b-4a3c-9d4e-5f6a7b8c9d0e", "resource": patient, "request": {"method": "POST", "url": "Patient"}},
+                {"request": {"method": "DELETE", "url": format!("Observation/{}", &obs_id), "ifMatch": "W/\"1\""}}
+            ]
+        });
+        let resp = api_base.bundle(bundle);
+        assert!(matches!(resp, Err(RaError::Custom{code: 412, ..})));
+        assert!(api_base.find_single_match(rd, vec![("family", "Rollback")])?.is_none());
+        let rd = api_base.schema.get_res_def_by_name("Observation")?;
+        assert_eq!("2", api_base.db.read(rd, &obs_id)?.get_document("meta")?.get_str("versionId")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_paging() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let mut data = read_patient();
+        data.pointer_mut("/name/0").unwrap().as_object_mut().unwrap().insert(String::from("family"), Value::String(String::from("Pager")));
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            if let RaResponse::Created(doc) = api_base.create("Patient", &data)? {
+                ids.push(doc.get_str("id")?.to_string());
+            }
+        }
+
+        let search = |page: Option<&str>, offset: u32| -> Result<SearchSet, RaError> {
+            let mut sq = SearchQuery::new(vec![("family", "Pager")]);
+            sq.count = 2;
+            sq.offset = offset;
+            sq.page = page;
+            match api_base.search_query("Patient", &sq, &ResponseHints::default())? {
+                RaResponse::SearchResult(ss) => Ok(ss),
+                _ => panic!("expected a searchset")
+            }
+        };
+        let ids_of = |ss: &SearchSet| -> Vec<String> {
+            ss.entries.iter().map(|e| e.resource.get_str("id").unwrap().to_string()).collect()
+        };
+
+        let first = search(None, 0)?;
+        assert_eq!(2, first.len());
+        assert!(first.prev_page.is_none());
+        assert_eq!(2, first.links.len());
+        assert_eq!("self", first.links[0].0);
+        assert!(first.links[0].1.starts_with("/Patient?family=Pager&_count=2"));
+        assert_eq!("next", first.links[1].0);
+
+        let second = search(first.next_page.as_deref(), 0)?;
+        assert_eq!(2, second.len());
+        let previous = search(second.prev_page.as_deref(), 0)?;
+        assert_eq!(ids_of(&first), ids_of(&previous));
+        let third = search(second.next_page.as_deref(), 0)?;
+        assert_eq!(1, third.len());
+        assert!(third.next_page.is_none());
+
+        let mut paged: Vec<String> = [ids_of(&first), ids_of(&second), ids_of(&third)].concat();
+        let offset = search(None, 4)?;
+        assert_eq!(paged[4..], ids_of(&offset)[..]);
+        paged.sort();
+        ids.sort();
+        assert_eq!(ids, paged);
+
+        // the pages resume from the last seen match even when more resources get created
+        api_base.create("Patient", &data)?;
+        let second = search(first.next_page.as_deref(), 0)?;
+        for id in ids_of(&second) {
+            assert!(!ids_of(&first).contains(&id));
+        }
+
+        let resp = search(Some("invalid"), 0);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_include() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let mut api_base = tc.setup_api_base_with_example_patient();
+        let mut sq = SearchQuery::new(vec![("status", "finished")]);
+        sq.include = vec![("_include", "Encounter:subject")];
+        let resp = search_entries(&api_base, "Encounter", &sq);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+
+        api_base.config.search_include = true;
+        api_base.config.search_rev_include = true;
+        let create = |res_name: &str, data: Value| create_resource(&api_base, res_name, data);
+        let patient = create("Patient", json!({"resourceType": "Patient", "name": [{"family": "Includer"}]}))?;
+        let practitioner = create("Practitioner", json!({"resourceType": "Practitioner", "name": [{"family": "Carer"}]}))?;
+        let encounter = create("Encounter", json!({"resourceType": "Encounter", "status": "finished",
+            "class": {"system": "http://terminology.hl7.org/CodeSystem/v3-ActCode", "code": "AMB"},
+            "subject": {"reference": patient}, "participant": [{"individual": {"reference": practitioner}}]}))?;
+
+        let search = |res_name: &str, params: Vec<(&str, &str)>, include: Vec<(&str, &str)>, revinclude: Vec<(&str, &str)>| -> Result<Vec<(String, bool)>, RaError> {
+            let mut sq = SearchQuery::new(params);
+            sq.include = include;
+            sq.revinclude = revinclude;
+            let entries = search_entries(&api_base, res_name, &sq)?;
+            Ok(entries.iter().map(|e| (to_reference(&e.resource), matches!(e.mode, SearchEntryMode::Include))).collect())
+        };
+
+        let entries = search("Encounter", vec![("status", "finished")], vec![("_include", "Encounter:subject"), ("_include", "Encounter:practitioner")], vec![])?;
+        assert_eq!(vec![(encounter.clone(), false), (patient.clone(), true), (practitioner.clone(), true)], entries);
+
+        let entries = search("Encounter", vec![("status", "finished")], vec![("_include", "Encounter:*:Practitioner")], vec![])?;
+        assert_eq!(vec![(encounter.clone(), false), (practitioner.clone(), true)], entries);
+
+        let entries = search("Patient", vec![("family", "Includer")], vec![], vec![("_revinclude", "Encounter:subject")])?;
+        assert_eq!(vec![(patient.clone(), false), (encounter.clone(), true)], entries);
+
+        let entries = search("Practitioner", vec![("family", "Carer")], vec![], vec![("_revinclude", "*")])?;
+        assert_eq!(vec![(practitioner.clone(), false), (encounter.clone(), true)], entries);
+
+        // the practitioner is reachable only by iterating over the included encounter
+        let entries = search("Patient", vec![("family", "Includer")], vec![("_include", "Encounter:practitioner")], vec![("_revinclude", "Encounter:subject")])?;
+        assert_eq!(2, entries.len());
+        let entries = search("Patient", vec![("family", "Includer")], vec![("_include:iterate", "Encounter:practitioner")], vec![("_revinclude", "Encounter:subject")])?;
+        assert_eq!(vec![(patient.clone(), false), (encounter.clone(), true), (practitioner.clone(), true)], entries);
+
+        let resp = search("Encounter", vec![("status", "finished")], vec![("_include", "Encounter:status")], vec![]);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_has() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let create = |res_name: &str, data: Value| create_resource(&api_base, res_name, data);
+        let encounter_data = |patient: &str| json!({"resourceType": "Encounter", "status": "finished",
+            "class": {"system": "http://terminology.hl7.org/CodeSystem/v3-ActCode", "code": "AMB"},
+            "subject": {"reference": patient}});
+        let observed = create("Patient", json!({"resourceType": "Patient", "name": [{"family": "Haser"}]}))?;
+        let unobserved = create("Patient", json!({"resourceType": "Patient", "name": [{"family": "Haser"}]}))?;
+        let encounter = create("Encounter", encounter_data(&observed))?;
+        create("Encounter", encounter_data(&unobserved))?;
+        create("Observation", json!({"resourceType": "Observation", "status": "final",
+            "code": {"coding": [{"system": "http://loinc.org", "code": "1234-5"}]},
+            "subject": {"reference": observed}, "encounter": {"reference": encounter}}))?;
+
+        let search = |params: Vec<(&str, &str)>| -> Result<Vec<String>, RaError> {
+            let entries = search_entries(&api_base, "Patient", &SearchQuery::new(params))?;
+            Ok(entries.iter().map(|e| to_reference(&e.resource)).collect())
+        };
+
+        assert_eq!(vec![observed.clone()], search(vec![("_has:Observation:patient:code", "1234-5")])?);
+        assert!(search(vec![("_has:Observation:patient:code", "9999-9")])?.is_empty());
+        assert_eq!(vec![observed.clone()], search(vec![("_has:Encounter:subject:_has:Observation:encounter:code", "http://loinc.org|1234-5")])?);
+        assert_eq!(2, search(vec![("_has:Encounter:subject:status", "finished")])?.len());
+        assert_eq!(vec![observed.clone()], search(vec![("family", "Haser"), ("_has:Observation:subject:code", "1234-5")])?);
+
+        let resp = search(vec![("_has:Observation:code:code", "1234-5")]);
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_sort() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let mut data = read_patient();
+        *data.pointer_mut("/name/0/given/0").unwrap() = Value::String(String::from("Sorter"));
+        for family in ["Mid", "Zeta", "Alpha"] {
+            *data.pointer_mut("/name/0/family").unwrap() = Value::String(String::from(family));
+            api_base.create("Patient", &data)?;
+            // lastUpdated has millisecond precision
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let search = |sort: Option<&str>| -> Result<Vec<String>, RaError> {
+            let mut sq = SearchQuery::new(vec![("given", "Sorter")]);
+            sq.sort = sort;
+            match api_base.search_query("Patient", &sq, &ResponseHints::default())? {
+                RaResponse::SearchResult(ss) => Ok(ss.entries.iter().map(|e| {
+                    let name = e.resource.get_array("name").unwrap()[0].as_document().unwrap();
+                    name.get_str("family").unwrap().to_string()
+                }).collect()),
+                _ => panic!("expected a searchset")
+            }
+        };
+
+        assert_eq!(vec!["Alpha", "Mid", "Zeta"], search(Some("family"))?);
+        assert_eq!(vec!["Zeta", "Mid", "Alpha"], search(Some("-family"))?);
+        assert_eq!(3, search(None)?.len());
+        assert_eq!(vec!["Mid", "Zeta", "Alpha"], search(Some("_lastUpdated"))?);
+        assert_eq!(vec!["Alpha", "Zeta", "Mid"], search(Some("-_lastUpdated"))?);
+        assert_eq!(search(Some("_lastUpdated"))?, search(None)?);
+
+        let resp = search(Some("organization"));
+        assert!(matches!(resp, Err(RaError::BadRequest(_))));
+        Ok(())
+    }
+
     #[test]
     fn test_operation_outcome_ser() {
         let oo = OperationOutcome::new_error(IssueType::Processing, "resource not found");
@@ -4,10 +4,12 @@ use bson::{Document};
 use ksuid::Ksuid;
 use serde::{Serialize, Serializer};
 use serde::ser::{SerializeMap, SerializeStruct};
-use serde_json::Value;
+use serde_json::{json, Value};
 
+use crate::api::base::OperationOutcome;
+use crate::barn::{is_tombstone, to_ksuid};
 use crate::errors::RaError;
-use crate::utils::bson_utils::get_str;
+use crate::utils::bson_utils::{get_int, get_str};
 
 pub struct RequestBundle {
     pub btype: BundleType,
@@ -15,7 +17,36 @@ pub struct RequestBundle {
 }
 
 pub struct SearchSet {
-    pub(crate) entries: Vec<SearchEntry>
+    pub(crate) entries: Vec<SearchEntry>,
+    /// number of matches across all pages, reported only when requested
+    pub(crate) total: Option<usize>,
+    /// continuation token of the next page
+    pub(crate) next_page: Option<String>,
+    /// continuation token of the previous page
+    pub(crate) prev_page: Option<String>,
+    /// relation and URL of the links
+    pub(crate) links: Vec<(&'static str, String)>
+}
+
+pub struct HistorySet {
+    pub(crate) entries: Vec<Document>,
+    pub(crate) base_url: String
+}
+
+/// the response to a batch or a transaction
+pub struct ResponseBundle {
+    pub(crate) btype: BundleType,
+    pub(crate) entries: Vec<ResponseEntry>
+}
+
+/// the outcome of processing an entry of a batch or a transaction
+pub struct ResponseEntry {
+    pub status: String,
+    pub location: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub resource: Option<Value>,
+    pub outcome: Option<OperationOutcome>
 }
 
 pub struct SearchEntry {
@@ -36,7 +67,15 @@ pub struct RequestEntry {
     pub full_url: String,
     pub resource: Document,
     #[serde(skip_serializing)]
-    pub ra_id: Ksuid
+    pub ra_id: Ksuid,
+    /// set to true when a resource matching the ifNoneExist condition of the entry already exists
+    #[serde(skip_serializing)]
+    pub exists: bool,
+    /// position of the entry in the request bundle
+    #[serde(skip_serializing)]
+    pub index: usize,
+    #[serde(skip_serializing)]
+    pub if_match: Option<String>
 }
 
 #[derive(Debug, Eq, PartialEq, Serialize)]
@@ -99,37 +138,139 @@ impl BundleType {
     }
 }
 
+impl BundleType {
+    pub fn code(&self) -> &'static str {
+        use self::BundleType::*;
+        match self {
+            Document => "document",
+            Message => "message",
+            Transaction => "transaction",
+            TransactionResponse => "transaction-response",
+            Batch => "batch",
+            BatchResponse => "batch-response",
+            History => "history",
+            SearchSet => "searchset",
+            Collection => "collection"
+        }
+    }
+}
+
+impl HistorySet {
+    pub fn new(entries: Vec<Document>, base_url: &str) -> Self {
+        Self{entries, base_url: base_url.trim_end_matches('/').to_string()}
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
 impl SearchSet {
     pub fn new() -> Self {
-        Self{entries: Vec::new()}
+        Self{entries: Vec::new(), total: None, next_page: None, prev_page: None, links: Vec::new()}
     }
 
     pub fn add(&mut self, d: Document) {
         self.entries.push(SearchEntry{resource: d, mode: SearchEntryMode::Match});
     }
 
+    pub fn add_include(&mut self, d: Document) {
+        self.entries.push(SearchEntry{resource: d, mode: SearchEntryMode::Include});
+    }
+
     pub fn len(&self) -> usize {
         self.entries.len()
     }
 }
 
+impl ResponseBundle {
+    pub fn new(btype: BundleType) -> Self {
+        Self{btype, entries: Vec::new()}
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl ResponseEntry {
+    pub fn new<S: AsRef<str>>(status: S) -> Self {
+        ResponseEntry{status: String::from(status.as_ref()), location: None, etag: None, last_modified: None, resource: None, outcome: None}
+    }
+
+    /// creates an entry with the version details of the given resource, the location is set
+    /// only for the resources that were created or updated
+    pub fn from_doc<S: AsRef<str>>(status: S, doc: &Document, with_location: bool, with_resource: bool) -> Self {
+        let mut entry = ResponseEntry::new(status);
+        let vid = get_int(doc, "meta.versionId");
+        if with_location {
+            entry.location = Some(format!("{}/{}/_history/{}", get_str(doc, "resourceType"), get_str(doc, "id"), vid));
+        }
+        entry.etag = Some(format!("W/\"{}\"", vid));
+        entry.last_modified = Some(String::from(get_str(doc, "meta.lastUpdated")));
+        if with_resource {
+            entry.resource = serde_json::to_value(doc).ok();
+        }
+        entry
+    }
+
+    pub fn from_error(status: String, outcome: OperationOutcome) -> Self {
+        let mut entry = ResponseEntry::new(status);
+        entry.outcome = Some(outcome);
+        entry
+    }
+}
+
 impl RequestBundle {
-    pub fn from(mut val: Value) -> Result<RequestBundle, RaError> {
+    pub fn from(val: Value) -> Result<RequestBundle, RaError> {
+        RequestBundle::from_with_existing(val, &HashMap::new())
+    }
+
+    /// the `existing` map holds the IDs of the resources, keyed by the fullUrl of the entries, that
+    /// matched the conditions given in ifNoneExist
+    pub fn from_with_existing(mut val: Value, existing: &HashMap<String, String>) -> Result<RequestBundle, RaError> {
         let btype = val.get("type").unwrap().as_str().unwrap();
         let btype = BundleType::from(btype)?;
 
         let entries = val.get_mut("entry").unwrap();
         let mut entries = entries.as_array_mut().unwrap();
-        let ref_links = RequestBundle::gather_refs(entries)?;
+        let ref_links = RequestBundle::gather_refs(entries, existing)?;
 
         let mut resources: Vec<RequestEntry> = Vec::new();
-        for item in entries {
-            let full_url = item.get("fullUrl").unwrap().as_str().unwrap();
-            let full_url = String::from(full_url);
+        for (index, item) in entries.iter_mut().enumerate() {
             let req_url = item.pointer("/request/url").unwrap().as_str().unwrap();
             let req_url = String::from(req_url);
             let req_method = item.pointer("/request/method").unwrap().as_str().unwrap();
             let req_method = Method::from(req_method)?;
+            let if_match = item.pointer("/request/ifMatch").and_then(|v| v.as_str()).map(|v| v.to_string());
+            let full_url = item.get("fullUrl").map_or("", |v| v.as_str().unwrap_or(""));
+            let full_url = String::from(full_url);
+            match req_method {
+                Method::Get | Method::Head => {
+                    // reads do not target a single resource, the ID is not used
+                    let e = RequestEntry { full_url, req_url, req_method, resource: Document::new(), ra_id: Ksuid::generate(), exists: false, index, if_match };
+                    resources.push(e);
+                    continue;
+                },
+                Method::Delete | Method::Patch => {
+                    // fullUrl is not required for deletes and patches, the target is identified by the request URL
+                    let (_, _, ra_id) = parse_req_url(&req_url)?;
+                    let mut resource = Document::new();
+                    if req_method == Method::Patch {
+                        // the resource of a patch entry is either a Binary holding a JSON Patch or a Parameters resource
+                        let resource_val = item.get("resource");
+                        if let None = resource_val {
+                            return Err(RaError::bad_req(format!("missing patch resource in the entry with request URL {}", &req_url)));
+                        }
+                        resource = bson::to_document(resource_val.unwrap())?;
+                    }
+                    let e = RequestEntry { full_url, req_url, req_method, resource, ra_id, exists: false, index, if_match };
+                    resources.push(e);
+                    continue;
+                },
+                _ => {}
+            }
+
             let resource_val = item.get_mut("resource").unwrap();
 
             if btype == BundleType::Transaction {
@@ -144,15 +285,16 @@ impl RequestBundle {
             if let None = ra_id {
                 return Err(RaError::bad_req(format!("no id found in the resource with fullUrl {}", full_url)));
             }
-            let ra_id = Ksuid::from_base62(ra_id.unwrap());
-            if let Err(e) = ra_id {
+            let ra_id = to_ksuid(ra_id.unwrap());
+            if let None = ra_id {
                 return Err(RaError::bad_req(format!("invalid id found in the resource with fullUrl {}", full_url)));
             }
             let ra_id = ra_id.unwrap();
 
             let resource = bson::to_document(resource_val).unwrap();
 
-            let e = RequestEntry { full_url, req_url, req_method, resource, ra_id };
+            let exists = existing.contains_key(&full_url);
+            let e = RequestEntry { full_url, req_url, req_method, resource, ra_id, exists, index, if_match };
             resources.push(e);
         }
 
@@ -163,58 +305,81 @@ impl RequestBundle {
         Ok(RequestBundle { btype, entries: resources })
     }
 
-    fn gather_refs(entries: &mut Vec<Value>) -> Result<Vec<(String, String, String, String)>, RaError> {
+    fn gather_refs(entries: &mut Vec<Value>, existing: &HashMap<String, String>) -> Result<Vec<(String, String, String, String)>, RaError> {
         let mut ref_links: Vec<(String, String, String, String)> = Vec::new();
 
         for item in entries {
             let req_method = item.pointer("/request/method").unwrap().as_str().unwrap();
             let req_method = Method::from(req_method)?;
-            let old_url = item.get("fullUrl").unwrap().as_str().unwrap().to_owned();
+            if req_method != Method::Post && req_method != Method::Put {
+                continue;
+            }
+            let req_url = item.pointer("/request/url").unwrap().as_str().unwrap().to_owned();
+            let old_url = item.get("fullUrl").map(|v| v.as_str().unwrap_or("").to_owned());
             let resource = item.get_mut("resource");
             if let None = resource {
-                return Err(RaError::bad_req(format!("missing resource in the entry with fullUrl {}", &old_url)));
+                return Err(RaError::bad_req(format!("missing resource in the entry with request URL {}", &req_url)));
             }
             let resource = resource.unwrap();
-            let res_name = resource.get("resourceType").unwrap().as_str().unwrap();
-            match req_method {
-                Method::Post | Method::Put | Method::Patch => {
-                    let new_id = Ksuid::generate().to_base62();
-                    let res_type = resource.get("resourceType").unwrap().as_str();
-                    if res_type.is_none() {
-                        return Err(RaError::bad_req(format!("missing resourceType in the entry with fullUrl {}", &old_url)));
-                    }
-
-                    let res_type = res_type.unwrap();
-                    let mut old_id= None;
-                    if old_url.starts_with("urn:uuid:") {
-                        old_id = Some(old_url.split_at(9).1.to_owned());
-                    }
-                    else {
-                        let url_val = url::Url::parse(&old_url);
-                        if let Err(e) = url_val {
-                            return Err(RaError::bad_req(format!("invalid URL in fullUrl {}", &old_url)));
-                        }
-                        let url_val = url_val.unwrap();
-                        let delim = format!("/{}/", res_name);
-                        let url_path = url_val.path();
-                        let mut parts = url_path.splitn(2, delim.as_str());
-                        if let Some(_) = parts.next() {
-                            if let Some(id) = parts.next() {
-                                old_id = Some(format!("{}/{}", res_name, id));
-                            }
-                        }
+            let res_type = resource.get("resourceType").and_then(|v| v.as_str());
+            if res_type.is_none() {
+                return Err(RaError::bad_req(format!("missing resourceType in the entry with request URL {}", &req_url)));
+            }
+            let res_type = res_type.unwrap().to_owned();
+
+            let new_id;
+            if req_method == Method::Put {
+                // the ID of the updated resource is given in the request URL
+                let (res_name, id, _) = parse_req_url(&req_url)?;
+                if res_name != res_type {
+                    return Err(RaError::bad_req(format!("received {}'s data for the request URL {}", &res_type, &req_url)));
+                }
+                new_id = id.to_string();
+                if let Some(body_id) = resource.get("id").and_then(|v| v.as_str()) {
+                    if body_id != new_id {
+                        return Err(RaError::bad_req(format!("id {} in the resource doesn't match with the id in the request URL {}", body_id, &req_url)));
                     }
+                }
+            }
+            else {
+                // references to a conditionally created entry must point to the existing resource
+                new_id = match old_url.as_ref().and_then(|u| existing.get(u)) {
+                    Some(id) => id.clone(),
+                    None => Ksuid::generate().to_base62()
+                };
+            }
+            resource.as_object_mut().unwrap().insert(String::from("id"), Value::String(new_id.clone()));
 
-                    if let None = old_id {
-                        return Err(RaError::bad_req(format!("couldn't extract ID from the fullUrl {}", &old_url)));
+            if let None = old_url {
+                continue;
+            }
+            let old_url = old_url.unwrap();
+            let mut old_id= None;
+            if old_url.starts_with("urn:uuid:") {
+                old_id = Some(old_url.split_at(9).1.to_owned());
+            }
+            else {
+                let url_val = url::Url::parse(&old_url);
+                if let Err(e) = url_val {
+                    return Err(RaError::bad_req(format!("invalid URL in fullUrl {}", &old_url)));
+                }
+                let url_val = url_val.unwrap();
+                let delim = format!("/{}/", &res_type);
+                let url_path = url_val.path();
+                let mut parts = url_path.splitn(2, delim.as_str());
+                if let Some(_) = parts.next() {
+                    if let Some(id) = parts.next() {
+                        old_id = Some(format!("{}/{}", &res_type, id));
                     }
+                }
+            }
 
-                    let new_url = format!("{}/{}", res_type, &new_id);
-                    ref_links.push((old_url, old_id.unwrap(), new_url, new_id.clone()));
-                    resource.as_object_mut().unwrap().insert(String::from("id"), Value::String(new_id));
-                },
-                _ => {}
+            if let None = old_id {
+                return Err(RaError::bad_req(format!("couldn't extract ID from the fullUrl {}", &old_url)));
             }
+
+            let new_url = format!("{}/{}", &res_type, &new_id);
+            ref_links.push((old_url, old_id.unwrap(), new_url, new_id));
         }
 
         Ok(ref_links)
@@ -251,6 +416,24 @@ impl RequestBundle {
     }
 }
 
+/// parses the request URL of the form [type]/[id] and returns the resource type, ID and the Ksuid the ID maps to
+pub fn parse_req_url(req_url: &str) -> Result<(&str, &str, Ksuid), RaError> {
+    let mut parts = req_url.trim_start_matches('/').splitn(2, "/");
+    let res_name = parts.next().unwrap();
+    let id = parts.next();
+    if let None = id {
+        return Err(RaError::bad_req(format!("missing resource ID in the request URL {}", req_url)));
+    }
+
+    let id = id.unwrap();
+    let ra_id = to_ksuid(id);
+    if let None = ra_id {
+        return Err(RaError::bad_req(format!("invalid resource ID {} in the request URL {}", id, req_url)));
+    }
+
+    Ok((res_name, id, ra_id.unwrap()))
+}
+
 impl Eq for RequestEntry {}
 impl PartialEq for RequestEntry {
     fn eq(&self, other: &Self) -> bool {
@@ -277,9 +460,113 @@ impl Serialize for SearchSet {
         state.serialize_field("resourceType", "Bundle");
         state.serialize_field("type", "searchset");
         state.serialize_field("id", &uuid::Uuid::new_v4().to_string());
-        state.serialize_field("count", &self.entries.len());
+        if let Some(total) = self.total {
+            state.serialize_field("total", &total);
+        }
+        if !self.links.is_empty() {
+            let links: Vec<Value> = self.links.iter().map(|(relation, url)| json!({"relation": relation, "url": url})).collect();
+            state.serialize_field("link", &links);
+        }
+
+        if !self.entries.is_empty() {
+            state.serialize_field("entry", &self.entries);
+        }
+        state.end()
+    }
+}
+
+impl Serialize for HistorySet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let mut state = serializer.serialize_struct("", 4)?;
+        state.serialize_field("resourceType", "Bundle");
+        state.serialize_field("type", BundleType::History.code());
+        state.serialize_field("id", &uuid::Uuid::new_v4().to_string());
+
+        let entries: Vec<HistoryEntry> = self.entries.iter().map(|d| HistoryEntry(d, &self.base_url)).collect();
+        state.serialize_field("entry", &entries);
+        state.end()
+    }
+}
+
+impl Serialize for ResponseBundle {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let mut state = serializer.serialize_struct("", 4)?;
+        state.serialize_field("resourceType", "Bundle");
+        state.serialize_field("type", self.btype.code());
+        state.serialize_field("id", &uuid::Uuid::new_v4().to_string());
+        state.serialize_field("entry", &self.entries);
+        state.end()
+    }
+}
+
+impl Serialize for ResponseEntry {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let mut state = serializer.serialize_map(Some(2))?;
+        if let Some(resource) = &self.resource {
+            state.serialize_entry("resource", resource);
+        }
+
+        let mut response = serde_json::Map::new();
+        response.insert(String::from("status"), Value::from(self.status.as_str()));
+        if let Some(location) = &self.location {
+            response.insert(String::from("location"), Value::from(location.as_str()));
+        }
+        if let Some(etag) = &self.etag {
+            response.insert(String::from("etag"), Value::from(etag.as_str()));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            response.insert(String::from("lastModified"), Value::from(last_modified.as_str()));
+        }
+        if let Some(outcome) = &self.outcome {
+            response.insert(String::from("outcome"), serde_json::to_value(outcome).unwrap_or(Value::Null));
+        }
+        state.serialize_entry("response", &response);
+        state.end()
+    }
+}
+
+/// a wrapper to serialize a version of the resource, along with the base URL, as an entry of the history bundle
+struct HistoryEntry<'a>(&'a Document, &'a str);
 
-        state.serialize_field("entries", &self.entries);
+impl Serialize for HistoryEntry<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let doc = self.0;
+        let base_url = self.1;
+        let res_name = get_str(doc, "resourceType");
+        let id = get_str(doc, "id");
+        let vid = get_int(doc, "meta.versionId");
+        let deleted = is_tombstone(doc);
+
+        let mut state = serializer.serialize_map(Some(4))?;
+        let res_url = format!("{}/{}", res_name, id);
+        let full_url = format!("{}/{}", base_url, res_url);
+        state.serialize_entry("fullUrl", full_url.as_str());
+        if !deleted {
+            state.serialize_entry("resource", doc);
+        }
+
+        let mut request = HashMap::new();
+        let mut response = HashMap::new();
+        if deleted {
+            request.insert("method", String::from("DELETE"));
+            request.insert("url", res_url);
+            response.insert("status", String::from("204 No Content"));
+        }
+        else if vid == 1 {
+            request.insert("method", String::from("POST"));
+            request.insert("url", String::from(res_name));
+            response.insert("status", String::from("201 Created"));
+        }
+        else {
+            request.insert("method", String::from("PUT"));
+            request.insert("url", res_url);
+            response.insert("status", String::from("200 OK"));
+        }
+        response.insert("etag", format!("W/\"{}\"", vid));
+        response.insert("lastModified", String::from(get_str(doc, "meta.lastUpdated")));
+
+        state.serialize_entry("request", &request);
+        state.serialize_entry("response", &response);
         state.end()
     }
 }
@@ -345,6 +632,42 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_conditional_create_refs() -> Result<(), anyhow::Error> {
+        let val = serde_json::json!({
+            "resourceType": "Bundle",
+            "type": "transaction",
+            "entry": [
+                {
+                    "fullUrl": "urn:uuid:61ebe359-bfdc-4613-8bf2-c5e300945f0a",
+                    "resource": {"resourceType": "Patient"},
+                    "request": {"method": "POST", "url": "Patient", "ifNoneExist": "identifier=http://acme.org|123"}
+                },
+                {
+                    "fullUrl": "urn:uuid:88f151c0-a954-468a-88bd-5ae15c08e059",
+                    "resource": {"resourceType": "Observation", "subject": {"reference": "urn:uuid:61ebe359-bfdc-4613-8bf2-c5e300945f0a"}},
+                    "request": {"method": "POST", "url": "Observation"}
+                }
+            ]
+        });
+
+        let existing_id = Ksuid::generate().to_base62();
+        let mut existing = HashMap::new();
+        existing.insert(String::from("urn:uuid:61ebe359-bfdc-4613-8bf2-c5e300945f0a"), existing_id.clone());
+        let bundle = RequestBundle::from_with_existing(val, &existing)?;
+        for e in &bundle.entries {
+            if get_str(&e.resource, "resourceType") == "Patient" {
+                assert!(e.exists);
+                assert_eq!(existing_id, e.ra_id.to_base62());
+            }
+            else {
+                assert!(!e.exists);
+                assert_eq!(format!("Patient/{}", existing_id), get_str(e.resource.get_document("subject")?, "reference"));
+            }
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_serialize_searchset() -> Result<(), anyhow::Error> {
         let mut ss = SearchSet::new();
@@ -1,11 +1,12 @@
 use bson::{Bson, Document};
 use chrono::Utc;
+use crate::config::Config;
 use crate::res_schema::SchemaDef;
 use crate::utils;
 
 pub const CAPABILITY_STATEMENT_ID: &str = "2BO62RJ5I2iw5lrVgJU0jzXKp6S";
 
-pub fn gen_capability_stmt(schema: &SchemaDef, base_url: &str) -> Document {
+pub fn gen_capability_stmt(schema: &SchemaDef, base_url: &str, config: &Config) -> Document {
     let mut doc = bson::Document::new();
     doc.insert("id", CAPABILITY_STATEMENT_ID);
     doc.insert("resourceType", "CapabilityStatement");
@@ -21,9 +22,10 @@ pub fn gen_capability_stmt(schema: &SchemaDef, base_url: &str) -> Document {
     formats.push(Bson::from("application/fhir+json"));
     formats.push(Bson::from("json"));
     doc.insert("format", Bson::Array(formats));
-    // let mut patch_formats = bson::Array::new();
-    // patch_formats.push(Bson::from("application/fhir+json"));
-    // doc.insert("patchFormat", patch_formats);
+    let mut patch_formats = bson::Array::new();
+    patch_formats.push(Bson::from("application/json-patch+json"));
+    patch_formats.push(Bson::from("application/fhir+json"));
+    doc.insert("patchFormat", patch_formats);
 
     // software
     let mut software = bson::Document::new();
@@ -38,7 +40,7 @@ pub fn gen_capability_stmt(schema: &SchemaDef, base_url: &str) -> Document {
     implementation.insert("url", base_url);
     doc.insert("implementation", implementation);
 
-    let interaction_codes = ["create", "read", "search-type"]; // "history-instance", "history-type", "patch", "update", "vread", "delete"
+    let interaction_codes = ["create", "read", "vread", "update", "delete", "history-instance", "history-type", "search-type", "patch"];
     let mut interaction = bson::Array::new();
     for c in interaction_codes {
         interaction.push(Bson::from(c));
@@ -47,21 +49,29 @@ pub fn gen_capability_stmt(schema: &SchemaDef, base_url: &str) -> Document {
     let mut search_include = bson::Array::new();
     search_include.push(Bson::from("*"));
 
+    let mut search_rev_include = bson::Array::new();
+    search_rev_include.push(Bson::from("*"));
+
     let mut resource = bson::Array::new();
     for (k, v) in &schema.resources {
         let mut res_doc = bson::Document::new();
         res_doc.insert("type", k);
         res_doc.insert("profile", format!("http://hl7.org/fhir/StructureDefinition/{}", k));
         res_doc.insert("interaction", &interaction);
-        res_doc.insert("versioning", "no-version");
-        res_doc.insert("readHistory", false);
-        res_doc.insert("updateCreate", false);
-        res_doc.insert("conditionalCreate", false);
-        res_doc.insert("conditionalRead", false);
-        res_doc.insert("conditionalUpdate", false);
-        res_doc.insert("conditionalDelete", false);
+        res_doc.insert("versioning", config.versioning.code());
+        res_doc.insert("readHistory", config.read_history);
+        res_doc.insert("updateCreate", config.update_create);
+        res_doc.insert("conditionalCreate", config.conditional_create);
+        res_doc.insert("conditionalRead", if config.conditional_read { "full-support" } else { "not-supported" });
+        res_doc.insert("conditionalUpdate", config.conditional_update);
+        res_doc.insert("conditionalDelete", if config.conditional_delete { "single" } else { "not-supported" });
         //res_doc.insert("referencePolicy", "enforced");
-        res_doc.insert("searchInclude", &search_include);
+        if config.search_include {
+            res_doc.insert("searchInclude", &search_include);
+        }
+        if config.search_rev_include {
+            res_doc.insert("searchRevInclude", &search_rev_include);
+        }
 
         let mut search_param = bson::Array::new();
         let res_search_params = schema.get_search_params_of(k);
@@ -82,6 +92,11 @@ pub fn gen_capability_stmt(schema: &SchemaDef, base_url: &str) -> Document {
     let mut rest_doc = bson::Document::new();
     rest_doc.insert("mode", "server");
     rest_doc.insert("resource", resource);
+    let mut system_interaction = bson::Array::new();
+    system_interaction.push(Bson::from("history-system"));
+    system_interaction.push(Bson::from("batch"));
+    system_interaction.push(Bson::from("transaction"));
+    rest_doc.insert("interaction", system_interaction);
 
     let mut rest = bson::Array::new();
     rest.push(Bson::Document(rest_doc));
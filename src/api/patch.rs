@@ -0,0 +1,638 @@
+use std::rc::Rc;
+use bson::Document;
+use rawbson::elem::{Element, ElementType};
+use serde_json::{Map, Value};
+use crate::errors::{EvalError, IssueType, RaError};
+use crate::rapath::element_utils::to_systype;
+use crate::rapath::engine::{eval, UnresolvableExecContext};
+use crate::rapath::expr::{Ast, Function};
+use crate::rapath::parser::parse_with_schema;
+use crate::rapath::scanner::scan_tokens;
+use crate::rapath::stypes::{Collection, SystemType};
+use crate::res_schema::SchemaDef;
+use crate::utils::bson_utils::get_str;
+
+const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+
+/// applies the operations of the given JSON Patch (RFC 6902) on the target document.
+/// The document is left in an undefined state if any of the operations fail, hence
+/// this must always be applied on a copy
+pub fn apply_json_patch(target: &mut Value, patch: &Value) -> Result<(), RaError> {
+    let ops = patch.as_array();
+    if let None = ops {
+        return Err(RaError::bad_req("JSON Patch must be an array of operations"));
+    }
+
+    for op in ops.unwrap() {
+        let name = get_member(op, "op")?;
+        let path = get_member(op, "path")?;
+        match name {
+            "add" => {
+                let value = get_value(op)?;
+                add(target, path, value.clone())?;
+            },
+            "remove" => {
+                remove(target, path)?;
+            },
+            "replace" => {
+                let value = get_value(op)?;
+                let existing = get_mut(target, path)?;
+                *existing = value.clone();
+            },
+            "move" => {
+                let from = get_member(op, "from")?;
+                if path.starts_with(from) && path[from.len()..].starts_with("/") {
+                    return Err(RaError::bad_req(format!("cannot move {} into one of its children {}", from, path)));
+                }
+                let value = remove(target, from)?;
+                add(target, path, value)?;
+            },
+            "copy" => {
+                let from = get_member(op, "from")?;
+                let value = get_mut(target, from)?.clone();
+                add(target, path, value)?;
+            },
+            "test" => {
+                let value = get_value(op)?;
+                let existing = get_mut(target, path)?;
+                if existing != value {
+                    return Err(RaError::precondition_failed(IssueType::Conflict, format!("test operation failed on the path {}", path)));
+                }
+            },
+            _ => {
+                return Err(RaError::bad_req(format!("unknown JSON Patch operation {}", name)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// an operation of the FHIRPath Patch
+struct FhirPathOp<'a> {
+    op_type: &'a str,
+    path: &'a str,
+    name: Option<&'a str>,
+    value: Option<Value>,
+    index: Option<usize>,
+    source: Option<usize>,
+    destination: Option<usize>
+}
+
+/// applies the operations of the given FHIRPath Patch, a Parameters resource, on the target resource.
+/// The elements are located by walking the segments of the FHIRPath expressions, the criteria of where()
+/// are evaluated against the BSON form of each candidate element.
+/// Like the JSON Patch, this must always be applied on a copy
+pub fn apply_fhirpath_patch(target: &mut Value, patch: &Value, sd: &SchemaDef) -> Result<(), RaError> {
+    let params = patch.get("parameter").and_then(|p| p.as_array());
+    if let None = params {
+        return Err(RaError::bad_req("no operations found in the FHIRPath Patch"));
+    }
+
+    let res_name = target.get("resourceType").and_then(|r| r.as_str()).unwrap_or("").to_string();
+    for p in params.unwrap() {
+        if p.get("name").and_then(|n| n.as_str()) != Some("operation") {
+            return Err(RaError::bad_req("parameters of the FHIRPath Patch must be named operation"));
+        }
+
+        let op = FhirPathOp::from(p)?;
+        let tokens = scan_tokens(op.path)?;
+        let ast = parse_with_schema(tokens, Some(sd))?;
+        match op.op_type {
+            "add" => {
+                let name = op.get_name()?;
+                let value = op.get_value()?;
+                let container = locate_single(target, &ast, op.path)?;
+                let collection = sd.get_prop(res_name.as_str(), element_path(&container, name).as_str()).map_or(false, |p| p.collection);
+                let parent = target.pointer_mut(&container).unwrap().as_object_mut();
+                if let None = parent {
+                    return Err(RaError::bad_req(format!("cannot add {} to the non-complex element at {}", name, op.path)));
+                }
+                let parent = parent.unwrap();
+                match parent.get_mut(name) {
+                    Some(Value::Array(a)) => {
+                        a.push(value);
+                    },
+                    Some(_) => {
+                        return Err(RaError::bad_req(format!("element {} already exists at {}", name, op.path)));
+                    },
+                    None => {
+                        let value = if collection { Value::Array(vec![value]) } else { value };
+                        parent.insert(name.to_string(), value);
+                    }
+                }
+            },
+            "insert" => {
+                let index = op.get_index(op.index, "index")?;
+                let value = op.get_value()?;
+                let pointer = locate_collection(target, &ast, op.path)?;
+                match target.pointer_mut(&pointer) {
+                    Some(Value::Array(a)) => {
+                        if index > a.len() {
+                            return Err(RaError::bad_req(format!("index {} is out of bounds of the collection at {}", index, op.path)));
+                        }
+                        a.insert(index, value);
+                    },
+                    Some(_) => {
+                        return Err(RaError::bad_req(format!("{} is not a collection", op.path)));
+                    },
+                    None => {
+                        if index != 0 {
+                            return Err(RaError::bad_req(format!("index {} is out of bounds of the collection at {}", index, op.path)));
+                        }
+                        add(target, &pointer, Value::Array(vec![value]))?;
+                    }
+                }
+            },
+            "delete" => {
+                let pointers = locate(target, &ast, op.path)?;
+                match pointers.len() {
+                    // deleting an element that doesn't exist is not an error
+                    0 => {},
+                    1 => {
+                        remove_element(target, &pointers[0])?;
+                    },
+                    _ => {
+                        return Err(RaError::bad_req(format!("multiple elements found at {}", op.path)));
+                    }
+                }
+            },
+            "replace" => {
+                let value = op.get_value()?;
+                let pointer = locate_single(target, &ast, op.path)?;
+                *target.pointer_mut(&pointer).unwrap() = value;
+            },
+            "move" => {
+                let source = op.get_index(op.source, "source")?;
+                let destination = op.get_index(op.destination, "destination")?;
+                let pointer = locate_collection(target, &ast, op.path)?;
+                match target.pointer_mut(&pointer) {
+                    Some(Value::Array(a)) => {
+                        if source >= a.len() || destination >= a.len() {
+                            return Err(RaError::bad_req(format!("source or destination is out of bounds of the collection at {}", op.path)));
+                        }
+                        let item = a.remove(source);
+                        a.insert(destination, item);
+                    },
+                    _ => {
+                        return Err(RaError::bad_req(format!("{} is not a collection", op.path)));
+                    }
+                }
+            },
+            _ => {
+                return Err(RaError::bad_req(format!("unknown FHIRPath Patch operation {}", op.op_type)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl<'a> FhirPathOp<'a> {
+    fn from(param: &'a Value) -> Result<Self, RaError> {
+        let mut op = FhirPathOp{op_type: "", path: "", name: None, value: None, index: None, source: None, destination: None};
+        let parts = param.get("part").and_then(|p| p.as_array());
+        if let Some(parts) = parts {
+            for p in parts {
+                match p.get("name").and_then(|n| n.as_str()).unwrap_or("") {
+                    "type" => op.op_type = p.get("valueCode").and_then(|v| v.as_str()).unwrap_or(""),
+                    "path" => op.path = p.get("valueString").and_then(|v| v.as_str()).unwrap_or(""),
+                    "name" => op.name = p.get("valueString").and_then(|v| v.as_str()),
+                    "value" => op.value = get_part_value(p),
+                    "index" => op.index = p.get("valueInteger").and_then(|v| v.as_u64()).map(|i| i as usize),
+                    "source" => op.source = p.get("valueInteger").and_then(|v| v.as_u64()).map(|i| i as usize),
+                    "destination" => op.destination = p.get("valueInteger").and_then(|v| v.as_u64()).map(|i| i as usize),
+                    _ => {}
+                }
+            }
+        }
+
+        if op.op_type.is_empty() || op.path.is_empty() {
+            return Err(RaError::bad_req("missing type or path in the FHIRPath Patch operation"));
+        }
+
+        Ok(op)
+    }
+
+    fn get_name(&self) -> Result<&'a str, RaError> {
+        if let None = self.name {
+            return Err(RaError::bad_req(format!("missing name in the {} operation", self.op_type)));
+        }
+        Ok(self.name.unwrap())
+    }
+
+    fn get_value(&self) -> Result<Value, RaError> {
+        if let None = self.value {
+            return Err(RaError::bad_req(format!("missing value in the {} operation", self.op_type)));
+        }
+        Ok(self.value.as_ref().unwrap().clone())
+    }
+
+    fn get_index(&self, index: Option<usize>, name: &str) -> Result<usize, RaError> {
+        if let None = index {
+            return Err(RaError::bad_req(format!("missing {} in the {} operation", name, self.op_type)));
+        }
+        Ok(index.unwrap())
+    }
+}
+
+/// reads the value[x] of the given part, complex values given as nested parts are assembled into an object
+fn get_part_value(part: &Value) -> Option<Value> {
+    if let Some(parts) = part.get("part").and_then(|p| p.as_array()) {
+        let mut obj = Map::new();
+        for p in parts {
+            let name = p.get("name").and_then(|n| n.as_str());
+            let value = get_part_value(p);
+            if let (Some(name), Some(value)) = (name, value) {
+                match obj.get_mut(name) {
+                    Some(Value::Array(a)) => a.push(value),
+                    Some(existing) => {
+                        let first = existing.take();
+                        *existing = Value::Array(vec![first, value]);
+                    },
+                    None => {
+                        obj.insert(name.to_string(), value);
+                    }
+                }
+            }
+        }
+        return Some(Value::Object(obj));
+    }
+
+    if let Some(part) = part.as_object() {
+        for (k, v) in part {
+            if k.starts_with("value") {
+                return Some(v.clone());
+            }
+        }
+    }
+
+    None
+}
+
+/// returns the JSON pointers of the elements selected by the given FHIRPath expression. The expression
+/// is walked one segment at a time keeping the pointer of each of the candidate elements
+fn locate(target: &Value, ast: &Ast, path: &str) -> Result<Vec<String>, RaError> {
+    walk(target, ast, vec![String::new()], path)
+}
+
+/// applies the given segment of the expression on the candidates, returns the pointers of the selected elements
+fn walk(target: &Value, ast: &Ast, candidates: Vec<String>, path: &str) -> Result<Vec<String>, RaError> {
+    match ast {
+        Ast::Path {name} => {
+            // the resource itself
+            if candidates.len() == 1 && candidates[0].is_empty() && target.get("resourceType").and_then(|r| r.as_str()) == Some(name.as_str()) {
+                return Ok(candidates);
+            }
+            Ok(child_pointers(target, candidates, name))
+        },
+        Ast::SubExpr {lhs, rhs} => {
+            let candidates = walk(target, lhs, candidates, path)?;
+            walk(target, rhs, candidates, path)
+        },
+        Ast::ArrayIndex {left, index} => {
+            let candidates = walk(target, left, candidates, path)?;
+            Ok(candidates.into_iter().nth(*index).into_iter().collect())
+        },
+        Ast::TypeCast {at_and_type_name, ..} => {
+            Ok(child_pointers(target, candidates, at_and_type_name))
+        },
+        Ast::Function {func: Function::NameAndArgs(name, _)} if name.as_str() == "where" => {
+            filter_candidates(target, ast, candidates)
+        },
+        _ => Err(RaError::bad_req(format!("unable to locate the elements at {}", path)))
+    }
+}
+
+/// evaluates the where() function on each of the candidates, returns the pointers of the candidates matching its criteria
+fn filter_candidates(target: &Value, func: &Ast, candidates: Vec<String>) -> Result<Vec<String>, RaError> {
+    let root_bytes = to_bson_bytes(target)?;
+    let mut matched = Vec::new();
+    for pointer in candidates {
+        // the candidate is wrapped in a document to read primitive values also as elements
+        let mut wrapper = Map::new();
+        wrapper.insert(String::from("v"), target.pointer(&pointer).cloned().unwrap_or(Value::Null));
+        let bytes = to_bson_bytes(&Value::Object(wrapper))?;
+        let wrapper = Element::new(ElementType::EmbeddedDocument, &bytes);
+        let item = wrapper.as_document().map_err(EvalError::from)?.get("v").map_err(EvalError::from)?.and_then(to_systype);
+        if let None = item {
+            continue;
+        }
+        let mut items = Collection::new();
+        items.push(Rc::new(item.unwrap()));
+
+        let root = Rc::new(SystemType::Element(Element::new(ElementType::EmbeddedDocument, &root_bytes)));
+        let ctx = UnresolvableExecContext::new(root);
+        let result = eval(&ctx, func, Rc::new(SystemType::Collection(items)))?;
+        if !result.is_empty() {
+            matched.push(pointer);
+        }
+    }
+
+    Ok(matched)
+}
+
+/// returns the pointer of the single element selected by the given expression
+fn locate_single(target: &Value, ast: &Ast, path: &str) -> Result<String, RaError> {
+    let mut pointers = locate(target, ast, path)?;
+    match pointers.len() {
+        0 => Err(RaError::bad_req(format!("no element found at {}", path))),
+        1 => Ok(pointers.remove(0)),
+        _ => Err(RaError::bad_req(format!("multiple elements found at {}", path)))
+    }
+}
+
+/// returns the pointer of the collection selected by the given expression, the collection may not exist yet
+fn locate_collection(target: &Value, ast: &Ast, path: &str) -> Result<String, RaError> {
+    match ast {
+        Ast::Path {name} => Ok(format!("/{}", escape(name))),
+        Ast::SubExpr {lhs, rhs} => {
+            if let Ast::Path {name} = rhs.as_ref() {
+                let parent = locate_single(target, lhs, path)?;
+                return Ok(format!("{}/{}", parent, escape(name)));
+            }
+            Err(RaError::bad_req(format!("path {} must end with the name of the collection", path)))
+        },
+        _ => Err(RaError::bad_req(format!("path {} must end with the name of the collection", path)))
+    }
+}
+
+/// returns the pointers of the children having the given name, each item of a collection is a separate child
+fn child_pointers(target: &Value, parents: Vec<String>, name: &str) -> Vec<String> {
+    let mut pointers = Vec::new();
+    for p in parents {
+        let child = format!("{}/{}", p, escape(name));
+        match target.pointer(&child) {
+            Some(Value::Array(a)) => {
+                for i in 0..a.len() {
+                    pointers.push(format!("{}/{}", &child, i));
+                }
+            },
+            Some(_) => pointers.push(child),
+            None => {}
+        }
+    }
+
+    pointers
+}
+
+/// removes the element at the given pointer, the parent collection is also removed if it becomes empty
+fn remove_element(target: &mut Value, pointer: &str) -> Result<(), RaError> {
+    remove(target, pointer)?;
+    let (parent, _) = split_pointer(pointer)?;
+    let empty = match target.pointer(parent) {
+        Some(Value::Array(a)) => a.is_empty(),
+        _ => false
+    };
+    if empty {
+        remove(target, parent)?;
+    }
+
+    Ok(())
+}
+
+/// converts the pointer of a container and the name of its child into the dotted path
+/// of the child's attribute, e.g /contact/0 and name into contact.name
+fn element_path(container: &str, name: &str) -> String {
+    let mut path: Vec<&str> = container.split("/").filter(|p| !p.is_empty() && p.parse::<usize>().is_err()).collect();
+    path.push(name);
+    path.join(".")
+}
+
+fn to_bson_bytes(val: &Value) -> Result<Vec<u8>, RaError> {
+    let doc = bson::to_document(val)?;
+    let mut bytes = Vec::new();
+    doc.to_writer(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn escape(token: &str) -> String {
+    token.replace("~", "~0").replace("/", "~1")
+}
+
+/// extracts the patch from the resource of a transaction bundle's entry. JSON Patches are
+/// carried in Binary resources with base64 encoded data
+pub fn get_patch_from_entry(resource: &Document) -> Result<Value, RaError> {
+    if get_str(resource, "resourceType") != "Binary" {
+        let val = serde_json::to_value(resource);
+        if let Err(e) = val {
+            return Err(RaError::bad_req(format!("invalid patch resource ({})", e)));
+        }
+        return Ok(val.unwrap());
+    }
+
+    let content_type = get_str(resource, "contentType");
+    if content_type != JSON_PATCH_CONTENT_TYPE {
+        return Err(RaError::bad_req(format!("unsupported content type {} of the patch in Binary", content_type)));
+    }
+
+    let data = base64::decode(get_str(resource, "data"));
+    if let Err(e) = data {
+        return Err(RaError::bad_req(format!("invalid base64 data in Binary ({})", e)));
+    }
+
+    let val = serde_json::from_slice(&data.unwrap());
+    if let Err(e) = val {
+        return Err(RaError::bad_req(format!("invalid JSON Patch in Binary ({})", e)));
+    }
+
+    Ok(val.unwrap())
+}
+
+fn get_member<'a>(op: &'a Value, name: &str) -> Result<&'a str, RaError> {
+    let val = op.get(name);
+    if let Some(val) = val {
+        if let Some(s) = val.as_str() {
+            return Ok(s);
+        }
+    }
+
+    Err(RaError::bad_req(format!("missing or invalid {} in the JSON Patch operation", name)))
+}
+
+fn get_value(op: &Value) -> Result<&Value, RaError> {
+    let val = op.get("value");
+    if let None = val {
+        return Err(RaError::bad_req("missing value in the JSON Patch operation"));
+    }
+
+    Ok(val.unwrap())
+}
+
+/// splits the JSON pointer into the pointer of the parent and the unescaped last token
+fn split_pointer(path: &str) -> Result<(&str, String), RaError> {
+    let pos = path.rfind("/");
+    if let None = pos {
+        return Err(RaError::bad_req(format!("invalid path {}", path)));
+    }
+
+    let (parent, last) = path.split_at(pos.unwrap());
+    let last = last[1..].replace("~1", "/").replace("~0", "~");
+    Ok((parent, last))
+}
+
+fn get_mut<'a>(target: &'a mut Value, path: &str) -> Result<&'a mut Value, RaError> {
+    let val = target.pointer_mut(path);
+    if let None = val {
+        return Err(RaError::bad_req(format!("path {} doesn't exist", path)));
+    }
+
+    Ok(val.unwrap())
+}
+
+fn add(target: &mut Value, path: &str, value: Value) -> Result<(), RaError> {
+    if path.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+
+    let (parent, last) = split_pointer(path)?;
+    let parent = get_mut(target, parent)?;
+    match parent {
+        Value::Object(m) => {
+            m.insert(last, value);
+        },
+        Value::Array(a) => {
+            if last == "-" {
+                a.push(value);
+            }
+            else {
+                let index = parse_index(&last, a.len() + 1, path)?;
+                a.insert(index, value);
+            }
+        },
+        _ => {
+            return Err(RaError::bad_req(format!("the parent of the path {} is neither an object nor an array", path)));
+        }
+    }
+
+    Ok(())
+}
+
+fn remove(target: &mut Value, path: &str) -> Result<Value, RaError> {
+    let (parent, last) = split_pointer(path)?;
+    let parent = get_mut(target, parent)?;
+    let removed = match parent {
+        Value::Object(m) => m.remove(&last),
+        Value::Array(a) => {
+            let index = parse_index(&last, a.len(), path)?;
+            Some(a.remove(index))
+        },
+        _ => None
+    };
+
+    if let None = removed {
+        return Err(RaError::bad_req(format!("path {} doesn't exist", path)));
+    }
+
+    Ok(removed.unwrap())
+}
+
+fn parse_index(token: &str, upper_bound: usize, path: &str) -> Result<usize, RaError> {
+    let index = token.parse::<usize>();
+    if let Ok(index) = index {
+        if index < upper_bound && (token == "0" || !token.starts_with("0")) {
+            return Ok(index);
+        }
+    }
+
+    Err(RaError::bad_req(format!("invalid array index in the path {}", path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::utils::test_utils::TestContainer;
+    use super::*;
+
+    #[test]
+    fn test_json_patch() -> Result<(), RaError> {
+        let mut target = json!({"resourceType": "Patient", "name": [{"family": "Chalmers", "given": ["Peter", "James"]}], "active": true, "a~b/c": 1});
+        let patch = json!([
+            {"op": "replace", "path": "/name/0/family", "value": "Windsor"},
+            {"op": "add", "path": "/name/0/given/1", "value": "Jim"},
+            {"op": "add", "path": "/name/-", "value": {"family": "Chalmers"}},
+            {"op": "remove", "path": "/active"},
+            {"op": "test", "path": "/a~0b~1c", "value": 1},
+            {"op": "copy", "from": "/name/0/given", "path": "/alias"},
+            {"op": "move", "from": "/alias", "path": "/nickname"}
+        ]);
+        apply_json_patch(&mut target, &patch)?;
+        let expected = json!({"resourceType": "Patient", "name": [{"family": "Windsor", "given": ["Peter", "Jim", "James"]}, {"family": "Chalmers"}], "a~b/c": 1, "nickname": ["Peter", "Jim", "James"]});
+        assert_eq!(expected, target);
+
+        let failures = vec![
+            json!([{"op": "test", "path": "/name/0/family", "value": "Chalmers"}]),
+            json!([{"op": "remove", "path": "/unknown"}]),
+            json!([{"op": "add", "path": "/name/5", "value": {}}]),
+            json!([{"op": "move", "from": "/name", "path": "/name/0"}]),
+            json!([{"op": "unknown", "path": "/name"}]),
+            json!({"op": "remove", "path": "/name"})
+        ];
+        for f in failures {
+            assert!(apply_json_patch(&mut target, &f).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fhirpath_patch() -> Result<(), anyhow::Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let sd = &api_base.schema;
+        let mut target = json!({
+            "resourceType": "Patient",
+            "active": true,
+            "birthDate": "1974-12-25",
+            "name": [{"use": "official", "family": "Chalmers", "given": ["Peter"]}, {"use": "usual", "given": ["Jim"]}],
+            "identifier": [{"value": "1"}, {"value": "2"}]
+        });
+        let patch = json!({
+            "resourceType": "Parameters",
+            "parameter": [
+                {"name": "operation", "part": [{"name": "type", "valueCode": "replace"}, {"name": "path", "valueString": "Patient.name.where(use = 'official').family"}, {"name": "value", "valueString": "Windsor"}]},
+                {"name": "operation", "part": [{"name": "type", "valueCode": "replace"}, {"name": "path", "valueString": "Patient.name[0].given.where($this = 'Peter')"}, {"name": "value", "valueString": "Pete"}]},
+                {"name": "operation", "part": [{"name": "type", "valueCode": "delete"}, {"name": "path", "valueString": "Patient.active"}]},
+                {"name": "operation", "part": [{"name": "type", "valueCode": "add"}, {"name": "path", "valueString": "Patient"}, {"name": "name", "valueString": "gender"}, {"name": "value", "valueCode": "male"}]},
+                {"name": "operation", "part": [{"name": "type", "valueCode": "add"}, {"name": "path", "valueString": "Patient"}, {"name": "name", "valueString": "telecom"},
+                    {"name": "value", "part": [{"name": "system", "valueCode": "phone"}, {"name": "value", "valueString": "555-0101"}]}]},
+                {"name": "operation", "part": [{"name": "type", "valueCode": "insert"}, {"name": "path", "valueString": "Patient.identifier"}, {"name": "index", "valueInteger": 0}, {"name": "value", "valueIdentifier": {"value": "0"}}]},
+                {"name": "operation", "part": [{"name": "type", "valueCode": "move"}, {"name": "path", "valueString": "Patient.identifier"}, {"name": "source", "valueInteger": 2}, {"name": "destination", "valueInteger": 0}]},
+                {"name": "operation", "part": [{"name": "type", "valueCode": "delete"}, {"name": "path", "valueString": "Patient.name.where(use = 'usual').given"}]}
+            ]
+        });
+        apply_fhirpath_patch(&mut target, &patch, sd)?;
+        let expected = json!({
+            "resourceType": "Patient",
+            "birthDate": "1974-12-25",
+            "gender": "male",
+            "telecom": [{"system": "phone", "value": "555-0101"}],
+            "name": [{"use": "official", "family": "Windsor", "given": ["Pete"]}, {"use": "usual"}],
+            "identifier": [{"value": "2"}, {"value": "0"}, {"value": "1"}]
+        });
+        assert_eq!(expected, target);
+
+        let failures = vec![
+            json!({"resourceType": "Parameters", "parameter": [{"name": "operation", "part": [{"name": "type", "valueCode": "replace"}, {"name": "path", "valueString": "Patient.name"}, {"name": "value", "valueString": "x"}]}]}),
+            json!({"resourceType": "Parameters", "parameter": [{"name": "operation", "part": [{"name": "type", "valueCode": "add"}, {"name": "path", "valueString": "Patient"}, {"name": "name", "valueString": "birthDate"}, {"name": "value", "valueDate": "1974-12-26"}]}]}),
+            json!({"resourceType": "Parameters", "parameter": [{"name": "operation", "part": [{"name": "type", "valueCode": "move"}, {"name": "path", "valueString": "Patient.identifier"}, {"name": "source", "valueInteger": 5}, {"name": "destination", "valueInteger": 0}]}]}),
+            json!({"resourceType": "Parameters", "parameter": [{"name": "operation", "part": [{"name": "type", "valueCode": "unknown"}, {"name": "path", "valueString": "Patient"}]}]})
+        ];
+        for f in failures {
+            assert!(apply_fhirpath_patch(&mut target, &f, sd).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_patch_from_binary() -> Result<(), RaError> {
+        let patch = json!([{"op": "remove", "path": "/active"}]);
+        let data = base64::encode(patch.to_string());
+        let binary = bson::doc! {"resourceType": "Binary", "contentType": JSON_PATCH_CONTENT_TYPE, "data": data};
+        assert_eq!(patch, get_patch_from_entry(&binary)?);
+
+        let binary = bson::doc! {"resourceType": "Binary", "contentType": "text/plain", "data": "abcd"};
+        assert!(get_patch_from_entry(&binary).is_err());
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
 use std::convert::Infallible;
 use std::io::{BufWriter, Cursor, Sink};
 use std::process::exit;
+use bson::Document;
 use chrono::Utc;
 use log::debug;
 
-use rocket::{Build, Config, Data, get, post, Request, Response, Rocket, routes, State, warn};
+use rocket::{Build, Config, Data, delete, get, patch, post, put, Request, Response, Rocket, routes, State, warn};
 use rocket::data::{DataStream, FromData};
 use rocket::fairing::AdHoc;
 use rocket::form::{Form, FromForm};
@@ -12,16 +13,16 @@ use rocket::http::{ContentType, Header, Status};
 use rocket::http::hyper::header::LAST_MODIFIED;
 use rocket::request::{FromRequest, Outcome};
 use rocket::response::content::Json;
-use rocket::response::Responder;
+use rocket::response::{Builder, Responder};
 use rocket::serde::Deserialize;
 use serde_json::Value;
 
-use crate::api::base::{ApiBase, ConditionalHeaders, Contained, ContainedType, OperationOutcome, RaResponse, ResponseHints, ReturnContent, SearchQuery, Total};
+use crate::api::base::{ApiBase, ConditionalHeaders, Contained, ContainedType, HistoryQuery, RaResponse, ResponseHints, ReturnContent, SearchQuery, Summary, Total};
 use crate::utils::bson_utils;
-use crate::errors::{IssueType, RaError};
+use crate::errors::RaError;
 
 const FHIR_JSON: &'static str = "application/fhir+json";
-const DATE_HEADER_FORMAT: &'static str = "%a, %d %m %Y %H:%M:%S GMT";
+const DATE_HEADER_FORMAT: &'static str = "%a, %d %b %Y %H:%M:%S GMT";
 
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for SearchQuery<'r> {
@@ -31,13 +32,15 @@ impl<'r> FromRequest<'r> for SearchQuery<'r> {
         let mut params: Vec<(&'r str, &'r str)> = Vec::new();
         let mut sort: Option<&'r str> = None;
         let mut count: u32 = 20;
-        let mut include: Option<&'r str> = None;
-        let mut revinclude: Option<&'r str> = None;
+        let mut offset: u32 = 0;
+        let mut page: Option<&'r str> = None;
+        let mut include: Vec<(&'r str, &'r str)> = Vec::new();
+        let mut revinclude: Vec<(&'r str, &'r str)> = Vec::new();
         let mut total = Total::None;
         let mut contained = Contained::DoNotReturn;
         let mut contained_type = ContainedType::Container;
         let mut elements = false;
-        let mut summary = false;
+        let mut summary = Summary::False;
         let mut ignore_unknown_params = false;
 
         for item in request.query_fields() {
@@ -46,10 +49,7 @@ impl<'r> FromRequest<'r> for SearchQuery<'r> {
                     continue;
                 },
                 "_summary" => {
-                    let tmp = item.value.parse::<bool>();
-                    if let Ok(b) = tmp {
-                        summary = b;
-                    }
+                    summary = Summary::from(item.value);
                 },
                 "_elements" => {
                     let tmp = item.value.parse::<bool>();
@@ -70,11 +70,23 @@ impl<'r> FromRequest<'r> for SearchQuery<'r> {
                         count = tmp.unwrap();
                     }
                 },
-                "_include" => {
-                    include = Some(item.value);
+                "_offset" => {
+                    let tmp = item.value.parse::<u32>();
+                    if let Err(e) = tmp {
+                        debug!("invalid value {} given for _offset parameter ({})", item.value, e.to_string());
+                    }
+                    else {
+                        offset = tmp.unwrap();
+                    }
                 },
-                "_revinclude" => {
-                    revinclude = Some(item.value);
+                "_page" => {
+                    page = Some(item.value);
+                },
+                name @ ("_include" | "_include:iterate" | "_include:recurse") => {
+                    include.push((name, item.value));
+                },
+                name @ ("_revinclude" | "_revinclude:iterate" | "_revinclude:recurse") => {
+                    revinclude.push((name, item.value));
                 },
                 "_total" => {
                     total = Total::from(item.value);
@@ -108,11 +120,47 @@ impl<'r> FromRequest<'r> for SearchQuery<'r> {
             }
         }
 
-        let sq = SearchQuery {params, sort, count, include, revinclude, summary, total, elements, contained, contained_type, ignore_unknown_params};
+        let sq = SearchQuery {params, sort, count, offset, page, include, revinclude, summary, total, elements, contained, contained_type, ignore_unknown_params};
         Outcome::Success(sq)
     }
 }
 
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for HistoryQuery<'r> {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let mut count: u32 = 100;
+        let mut since: Option<&'r str> = None;
+        let mut at: Option<&'r str> = None;
+
+        for item in request.query_fields() {
+            match item.name.as_name().as_str() {
+                "_count" => {
+                    let tmp = item.value.parse::<u32>();
+                    if let Err(e) = tmp {
+                        debug!("invalid value {} given for _count parameter ({})", item.value, e.to_string());
+                    }
+                    else {
+                        count = tmp.unwrap();
+                    }
+                },
+                "_since" => {
+                    since = Some(item.value);
+                },
+                "_at" => {
+                    at = Some(item.value);
+                },
+                _ => {
+                    continue;
+                }
+            }
+        }
+
+        Outcome::Success(HistoryQuery{count, since, at})
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for &'r ResponseHints {
     type Error = RaError;
@@ -168,8 +216,12 @@ impl<'r> FromRequest<'r> for ConditionalHeaders<'r> {
     type Error = RaError;
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let mut h = request.headers().get("If-None-Exist");
-        let mut ch = ConditionalHeaders{if_none_exist: h.next()};
+        let headers = request.headers();
+        let if_none_exist = headers.get_one("If-None-Exist");
+        let if_match = headers.get_one("If-Match");
+        let if_none_match = headers.get_one("If-None-Match");
+        let if_modified_since = headers.get_one("If-Modified-Since");
+        let mut ch = ConditionalHeaders{if_none_exist, if_match, if_none_match, if_modified_since};
         Outcome::Success(ch)
     }
 }
@@ -178,41 +230,9 @@ impl<'r> FromRequest<'r> for ConditionalHeaders<'r> {
 impl<'r, 'o: 'r> Responder<'r, 'o> for RaError {
     fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'o> {
         let now = Utc::now().format(DATE_HEADER_FORMAT).to_string();
-        let status: Status;
-        let oo: OperationOutcome;
-
-        match self {
-            RaError::DbError(s) | RaError::SchemaParsingError(s) => {
-                oo = OperationOutcome::new_error(IssueType::Exception, s);
-                status = Status::InternalServerError;
-            },
-            RaError::SchemaValidationError => {
-                oo = OperationOutcome::new_error(IssueType::Processing, "schema validation failed");
-                status = Status::InternalServerError;
-            },
-            RaError::SearchParamParsingError(s) => {
-                oo = OperationOutcome::new_error(IssueType::Processing, s);
-                status = Status::InternalServerError;
-            },
-            RaError::BadRequest(s) => {
-                oo = OperationOutcome::new_error(IssueType::Processing, s);
-                status = Status::BadRequest;
-            },
-            RaError::NotFound(s) => {
-                oo = OperationOutcome::new_error(IssueType::Not_found, s);
-                status = Status::NotFound;
-            },
-            RaError::Custom {code, outcome} => {
-                oo = outcome;
-                if let Some(code) = Status::from_code(code) {
-                    status = code;
-                }
-                else {
-                    // fallback to 500 if the code is unknown
-                    status = Status::InternalServerError;
-                }
-            }
-        }
+        let (code, oo) = self.to_outcome();
+        // fallback to 500 if the code is unknown
+        let status = Status::from_code(code).unwrap_or(Status::InternalServerError);
 
         let mut resp = Response::build_from(Response::new());
         resp.raw_header("Date", now);
@@ -234,31 +254,54 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for RaResponse {
 
         match self {
             RaResponse::Created(doc) => {
-                let id = doc.get_str("id").unwrap();
-                let res_type = doc.get_str("resourceType").unwrap();
-                let vid = bson_utils::get_int(&doc, "meta.versionId");
+                resp.status(Status::Created);
+                set_location_headers(&mut resp, &doc);
+                if hints.rturn == ReturnContent::Representation {
+                    let buf = to_json_body(&doc, hints.pretty);
+                    resp.raw_header("Content-Type", FHIR_JSON)
+                        .sized_body(buf.len(), Cursor::new(buf));
+                }
 
-                // let cfg = request.rocket().config();
-                let loc = format!("{}/{}/_history/{}", res_type, id, vid);
+                resp.ok()
+            },
+            RaResponse::Updated(doc) => {
+                resp.status(Status::Ok);
+                set_location_headers(&mut resp, &doc);
+                if hints.rturn == ReturnContent::Representation {
+                    let buf = to_json_body(&doc, hints.pretty);
+                    resp.raw_header("Content-Type", FHIR_JSON)
+                        .sized_body(buf.len(), Cursor::new(buf));
+                }
 
-                let last_modified = bson_utils::get_time(&doc, "meta.lastUpdated").unwrap();
-                //Last-Modified: <day-name>, <day> <month> <year> <hour>:<minute>:<second> GMT
-                let last_modified = last_modified.format(DATE_HEADER_FORMAT).to_string();
+                resp.ok()
+            },
+            RaResponse::Deleted => {
+                resp.status(Status::NoContent)
+                    .ok()
+            },
+            RaResponse::Resource(doc) => {
+                let vid = bson_utils::get_int(&doc, "meta.versionId");
+                resp.status(Status::Ok)
+                    .raw_header("Content-Type", FHIR_JSON)
+                    .raw_header("ETag", format!("W/\"{}\"", vid));
 
-                resp.status(Status::Created)
-                    .raw_header("Location", loc)
-                    .raw_header("Etag", vid.to_string())
-                    .raw_header("Last-Modified", last_modified);
+                let last_modified = bson_utils::get_time(&doc, "meta.lastUpdated");
+                if let Some(last_modified) = last_modified {
+                    resp.raw_header("Last-Modified", last_modified.format(DATE_HEADER_FORMAT).to_string());
+                }
 
-                if hints.rturn == ReturnContent::Representation {
-                    let buf;
-                    if hints.pretty {
-                        buf = serde_json::to_vec_pretty(&doc).unwrap();
-                    }
-                    else {
-                        buf = serde_json::to_vec(&doc).unwrap();
-                    }
-                    resp.sized_body(buf.len(), Cursor::new(buf));
+                let buf = to_json_body(&doc, hints.pretty);
+                resp.sized_body(buf.len(), Cursor::new(buf))
+                    .ok()
+            },
+            RaResponse::NotModified(doc) => {
+                let vid = bson_utils::get_int(&doc, "meta.versionId");
+                resp.status(Status::NotModified)
+                    .raw_header("ETag", format!("W/\"{}\"", vid));
+
+                let last_modified = bson_utils::get_time(&doc, "meta.lastUpdated");
+                if let Some(last_modified) = last_modified {
+                    resp.raw_header("Last-Modified", last_modified.format(DATE_HEADER_FORMAT).to_string());
                 }
 
                 resp.ok()
@@ -278,11 +321,49 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for RaResponse {
                     .raw_header("Content-Type", FHIR_JSON)
                     .sized_body(buf.len(), Cursor::new(buf))
                     .ok()
+            },
+            RaResponse::History(hs) => {
+                let buf = serde_json::to_vec(&hs).unwrap();
+                resp.status(Status::Ok)
+                    .raw_header("Content-Type", FHIR_JSON)
+                    .sized_body(buf.len(), Cursor::new(buf))
+                    .ok()
+            },
+            RaResponse::Bundle(rb) => {
+                let buf = serde_json::to_vec(&rb).unwrap();
+                resp.status(Status::Ok)
+                    .raw_header("Content-Type", FHIR_JSON)
+                    .sized_body(buf.len(), Cursor::new(buf))
+                    .ok()
             }
         }
     }
 }
 
+fn set_location_headers(resp: &mut Builder<'_>, doc: &Document) {
+    let id = doc.get_str("id").unwrap();
+    let res_type = doc.get_str("resourceType").unwrap();
+    let vid = bson_utils::get_int(&doc, "meta.versionId");
+
+    // let cfg = request.rocket().config();
+    let loc = format!("{}/{}/_history/{}", res_type, id, vid);
+
+    let last_modified = bson_utils::get_time(&doc, "meta.lastUpdated").unwrap();
+    //Last-Modified: <day-name>, <day> <month> <year> <hour>:<minute>:<second> GMT
+    let last_modified = last_modified.format(DATE_HEADER_FORMAT).to_string();
+
+    resp.raw_header("Location", loc)
+        .raw_header("ETag", format!("W/\"{}\"", vid))
+        .raw_header("Last-Modified", last_modified);
+}
+
+fn to_json_body(doc: &Document, pretty: bool) -> Vec<u8> {
+    if pretty {
+        return serde_json::to_vec_pretty(doc).unwrap();
+    }
+    serde_json::to_vec(doc).unwrap()
+}
+
 pub fn mount(api_base: ApiBase, config: Config) -> Result<Rocket<Build>, anyhow::Error> {
     let base_url = url::Url::parse(&api_base.base_url);
     if let Err(e) = base_url {
@@ -296,7 +377,7 @@ pub fn mount(api_base: ApiBase, config: Config) -> Result<Rocket<Build>, anyhow:
         log_mdc::insert("request_id", uuid::Uuid::new_v4().to_string());
     }
     )));
-    Ok(server.mount(base, routes![create, bundle, search, metadata]))
+    Ok(server.mount(base, routes![create, bundle, search, read, vread, update, conditional_update, patch, delete, conditional_delete, history_instance, history_type, history_system, metadata]))
 }
 
 fn parse_input(d: &[u8]) -> Result<Value, RaError> {
@@ -310,13 +391,44 @@ fn parse_input(d: &[u8]) -> Result<Value, RaError> {
 #[post("/<res_name>", data = "<data>")]
 pub fn create(res_name: &str, data: &[u8], hints: &ResponseHints, ch: ConditionalHeaders<'_>, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
     let val = parse_input(data)?;
+    if let Some(if_none_exist) = ch.if_none_exist {
+        return base.create_if_none_exist(res_name, &val, if_none_exist);
+    }
     base.create(res_name, &val)
 }
 
+#[put("/<res_name>/<id>", data = "<data>")]
+pub fn update(res_name: &str, id: &str, data: &[u8], hints: &ResponseHints, ch: ConditionalHeaders<'_>, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
+    let val = parse_input(data)?;
+    base.update(res_name, id, &val, ch.if_match)
+}
+
+#[put("/<res_name>", data = "<data>")]
+pub fn conditional_update(res_name: &str, query: SearchQuery, data: &[u8], hints: &ResponseHints, ch: ConditionalHeaders<'_>, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
+    let val = parse_input(data)?;
+    base.conditional_update(res_name, &query, &val, ch.if_match)
+}
+
+#[patch("/<res_name>/<id>", data = "<data>")]
+pub fn patch(res_name: &str, id: &str, data: &[u8], hints: &ResponseHints, ch: ConditionalHeaders<'_>, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
+    let val = parse_input(data)?;
+    base.patch(res_name, id, &val, ch.if_match)
+}
+
+#[delete("/<res_name>")]
+pub fn conditional_delete(res_name: &str, query: SearchQuery, ch: ConditionalHeaders<'_>, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
+    base.conditional_delete(res_name, &query, ch.if_match)
+}
+
+#[delete("/<res_name>/<id>")]
+pub fn delete(res_name: &str, id: &str, ch: ConditionalHeaders<'_>, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
+    base.delete(res_name, id, ch.if_match)
+}
+
 #[post("/", data = "<data>")]
 pub fn bundle(data: &[u8], hints: &ResponseHints, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
     let val = parse_input(data)?;
-    base.bundle(val)
+    base.bundle_with_hints(val, hints)
 }
 
 #[get("/<res_name>")]
@@ -325,6 +437,33 @@ pub fn search(res_name: &str, query: SearchQuery, hints: &ResponseHints, base: &
     base.search_query(res_name, &query, hints)
 }
 
+#[get("/<res_name>/<id>")]
+pub fn read(res_name: &str, id: &str, hints: &ResponseHints, ch: ConditionalHeaders<'_>, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
+    let resp = base.read(res_name, id)?;
+    Ok(base.conditional_read(resp, &ch))
+}
+
+#[get("/<res_name>/<id>/_history/<vid>")]
+pub fn vread(res_name: &str, id: &str, vid: &str, hints: &ResponseHints, ch: ConditionalHeaders<'_>, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
+    let resp = base.vread(res_name, id, vid)?;
+    Ok(base.conditional_read(resp, &ch))
+}
+
+#[get("/<res_name>/<id>/_history")]
+pub fn history_instance(res_name: &str, id: &str, query: HistoryQuery, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
+    base.history_instance(res_name, id, &query)
+}
+
+#[get("/<res_name>/_history")]
+pub fn history_type(res_name: &str, query: HistoryQuery, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
+    base.history_type(res_name, &query)
+}
+
+#[get("/_history")]
+pub fn history_system(query: HistoryQuery, base: &State<ApiBase>) -> Result<RaResponse, RaError> {
+    base.history_system(&query)
+}
+
 #[get("/metadata")]
 pub fn metadata(base: &State<ApiBase>) -> Result<RaResponse, RaError> {
     debug!("returning CapabilityStatement for metadata request");
@@ -20,12 +20,14 @@ use log::{debug, error, info, trace, warn};
 use rawbson::de::BsonDeserializer;
 use rawbson::{Doc, DocBuf};
 use rawbson::elem::Element;
-use rocksdb::{DB, DBCompressionType, DBIterator, DBPinnableSlice, Env, Options, WriteBatch};
+use regex::Regex;
+use rocksdb::{DB, DBCompressionType, DBIterator, DBPinnableSlice, Direction, Env, IteratorMode, Options, WriteBatch};
 use serde_json::Value;
 use thiserror::private::PathAsDisplay;
+use uuid::Uuid;
 use crate::api::bundle::SearchSet;
 
-use crate::errors::{EvalError, RaError};
+use crate::errors::{EvalError, IssueType, RaError};
 use crate::rapath::engine::{eval, ExecContext, UnresolvableExecContext};
 use crate::rapath::EvalResult;
 use crate::rapath::expr::Ast;
@@ -36,9 +38,19 @@ use crate::utils;
 use crate::utils::{bson_utils, get_crc_hash, prefix_id};
 
 mod insert;
+mod update;
+mod delete;
+mod history;
 
 const RA_METADATA_KEY_PREFIX: &str = "_____RA_METADATA_KEY_PREFIX_____";
 pub(crate) const CF_INDEX: &str = "index";
+/// name of the attribute that marks a version in history as the tombstone of a deleted resource
+pub(crate) const TOMBSTONE_ATTR: &str = "_raDeleted";
+
+/// resources written to a WriteBatch but not yet committed, keyed by their primary keys.
+/// These are consulted while resolving references so that all the writes of a transaction,
+/// including the index rows, can be committed in a single batch
+pub type StagedResources = HashMap<[u8; 24], Vec<u8>>;
 
 lazy_static! {
  static ref SCHEMA_ID: [u8; 24] = {
@@ -47,7 +59,18 @@ lazy_static! {
         prefix_id(&prefix, schema_id.as_bytes())
     };
 
+ /// the format of the logical ID of a resource
+ static ref FHIR_ID: Regex = Regex::new(r"^[A-Za-z0-9\-\.]{1,64}$").unwrap();
+
  static ref SEARCH_PARAM_RESOURCE_KEY_PREFIX: [u8; 4] = get_crc_hash("SearchParameter");
+
+ /// prefix of the keys in the changelog, every version of every resource gets an entry
+ /// <prefix><lastUpdated-millis(big endian)><pk><version(big endian)>
+ static ref CHANGELOG_KEY_PREFIX: [u8; 4] = get_crc_hash("_____RA_CHANGELOG_KEY_PREFIX_____");
+
+ /// prefix of the keys holding the lastUpdated time of the current version of each resource, used for sorting
+ /// <prefix><pk> => <lastUpdated-millis(little endian)>
+ static ref LAST_UPDATED_KEY_PREFIX: [u8; 4] = get_crc_hash("_____RA_LAST_UPDATED_KEY_PREFIX_____");
 }
 
 pub struct Barn {
@@ -56,6 +79,13 @@ pub struct Barn {
     opts: Options
 }
 
+/// the versions of the resources read while preparing a batch, the batch is saved only
+/// if these are still the current versions. None stands for a resource that didn't exist
+#[derive(Default)]
+pub struct VersionChecks {
+    versions: Vec<([u8; 24], Option<u32>)>
+}
+
 /// used only for internal testing purpose
 /// this iterator copies the memory before returning an item
 pub struct ResourceIterator<'d> {
@@ -77,7 +107,8 @@ pub struct IndexRow<'a> {
 pub struct ResolvableContext<'b> {
     root: Rc<SystemType<'b>>,
     db: &'b Barn,
-    sd: &'b SchemaDef
+    sd: &'b SchemaDef,
+    staged: Option<&'b StagedResources>
 }
 
 impl<'b> ExecContext<'b> for ResolvableContext<'b> {
@@ -86,13 +117,17 @@ impl<'b> ExecContext<'b> for ResolvableContext<'b> {
     }
 
     fn resolve(&'b self, relative_url: &str) -> Result<Vec<u8>, EvalError> {
-        self.db.resolve(relative_url, self.sd)
+        self.db.resolve(relative_url, self.sd, self.staged)
     }
 }
 
 impl<'b> ResolvableContext<'b> {
     pub fn new(root: Rc<SystemType<'b>>, db: &'b Barn, sd: &'b SchemaDef) -> Self {
-        ResolvableContext{root, db, sd}
+        ResolvableContext{root, db, sd, staged: None}
+    }
+
+    pub fn with_staged(root: Rc<SystemType<'b>>, db: &'b Barn, sd: &'b SchemaDef, staged: Option<&'b StagedResources>) -> Self {
+        ResolvableContext{root, db, sd, staged}
     }
 }
 
@@ -257,6 +292,111 @@ impl Barn {
         Ok(res.unwrap())
     }
 
+    /// reads the current version of the resource with the given ID
+    pub fn read(&self, rd: &ResourceDef, id: &str) -> Result<Document, RaError> {
+        let ksid = parse_res_id(rd, id)?;
+        let pk = rd.new_id(ksid.as_bytes());
+        let res = self.get_resource_by_pk(&pk)?;
+        if let Some(res) = res {
+            return to_document(res.as_ref());
+        }
+
+        // the resource was deleted if there is no current version but the history exists
+        let latest = self.get_latest_history_entry(rd, ksid.as_bytes())?;
+        if let Some(_) = latest {
+            return Err(RaError::gone(format!("{}/{} was deleted", &rd.name, id)));
+        }
+
+        Err(RaError::not_found(format!("{}/{} not found", &rd.name, id)))
+    }
+
+    /// reads the given version of the resource, the current version is read from the resource's keyspace
+    /// and all the older versions are read from the history keyspace
+    pub fn vread(&self, rd: &ResourceDef, id: &str, version: u32) -> Result<Document, RaError> {
+        let ksid = parse_res_id(rd, id)?;
+        let doc = self.get_version(rd, ksid.as_bytes(), version)?;
+        if let Some(doc) = doc {
+            if is_tombstone(&doc) {
+                return Err(RaError::gone(format!("version {} of {}/{} was deleted", version, &rd.name, id)));
+            }
+            return Ok(doc);
+        }
+
+        Err(RaError::not_found(format!("version {} of {}/{} not found", version, &rd.name, id)))
+    }
+
+    /// returns the given version of the resource, None is returned if that version is not retained
+    fn get_version(&self, rd: &ResourceDef, ksid: &[u8], version: u32) -> Result<Option<Document>, RaError> {
+        let pk = rd.new_id(ksid);
+        let current = self.get_resource_by_pk(&pk)?;
+        if let Some(current) = current {
+            let doc = to_document(current.as_ref())?;
+            if bson_utils::get_int(&doc, "meta.versionId") == version as i64 {
+                return Ok(Some(doc));
+            }
+        }
+
+        self.get_history_entry(rd, ksid, version)
+    }
+
+    /// returns the given version of the resource from the history keyspace
+    fn get_history_entry(&self, rd: &ResourceDef, ksid: &[u8], version: u32) -> Result<Option<Document>, RaError> {
+        let history_pk = rd.new_history_version_id(ksid, version);
+        let res = self.db.get_pinned(&history_pk)?;
+        if let Some(res) = res {
+            return Ok(Some(to_document(res.as_ref())?));
+        }
+
+        Ok(None)
+    }
+
+    /// adds an entry to the changelog for the given version of the resource
+    fn log_change(&self, wb: &mut WriteBatch, pk: &[u8; 24], doc: &Document) {
+        let version = bson_utils::get_int(doc, "meta.versionId") as u32;
+        let millis = match bson_utils::get_time(doc, "meta.lastUpdated") {
+            Some(t) => t.timestamp_millis() as u64,
+            None => Utc::now().timestamp_millis() as u64
+        };
+
+        let mut key = [0; 40];
+        key[..4].copy_from_slice(&*CHANGELOG_KEY_PREFIX);
+        key[4..12].copy_from_slice(&millis.to_be_bytes());
+        key[12..36].copy_from_slice(pk);
+        key[36..].copy_from_slice(&version.to_be_bytes());
+        wb.put(&key, &[]);
+
+        let cf = self.db.cf_handle(CF_INDEX).unwrap();
+        let lu_key = new_last_updated_key(pk);
+        if is_tombstone(doc) {
+            wb.delete_cf(cf, &lu_key);
+        }
+        else {
+            wb.put_cf(cf, &lu_key, &(millis as i64).to_le_bytes());
+        }
+    }
+
+    /// returns the lastUpdated time, in milliseconds, of the current version of the resource.
+    /// None is returned for the resources written before these keys were introduced
+    pub fn get_last_updated(&self, pk: &[u8; 24]) -> Result<Option<i64>, RaError> {
+        let cf = self.db.cf_handle(CF_INDEX).unwrap();
+        let val = self.db.get_pinned_cf(cf, &new_last_updated_key(pk))?;
+        Ok(val.and_then(|v| v.as_ref().try_into().ok()).map(i64::from_le_bytes))
+    }
+
+    /// returns the most recent version of the resource present in the history keyspace
+    fn get_latest_history_entry(&self, rd: &ResourceDef, ksid: &[u8]) -> Result<Option<Document>, RaError> {
+        let prefix = rd.new_history_id(ksid);
+        let from = rd.new_history_version_id(ksid, u32::MAX);
+        let mut itr = self.db.iterator(IteratorMode::From(&from, Direction::Reverse));
+        if let Some((k, v)) = itr.next() {
+            if k.starts_with(&prefix) {
+                return Ok(Some(to_document(v.as_ref())?));
+            }
+        }
+
+        Ok(None)
+    }
+
     pub fn search<'a>(&self, res_def: &ResourceDef, filter: &'a Ast<'a>) -> Result<SearchSet, EvalError> {
         let mut results = SearchSet::new();
 
@@ -287,12 +427,33 @@ impl Barn {
         Ok(results)
     }
 
-    pub fn save_batch(&self, wb: WriteBatch) -> Result<(), RaError> {
+    /// saves the batch if none of the resources it modifies were changed after their versions were
+    /// read, fails with a 409 otherwise so that the client can retry with the latest versions
+    pub fn save_batch(&self, wb: WriteBatch, checks: &VersionChecks) -> Result<(), RaError> {
+        for (pk, expected) in &checks.versions {
+            let current = self.get_current_version(pk)?;
+            if current != *expected {
+                debug!("expected version {:?} but found {:?}", expected, current);
+                return Err(RaError::conflict("the resource was modified by a concurrent request"));
+            }
+        }
+
         debug!("saving batch");
         self.db.write(wb)?;
         Ok(())
     }
 
+    /// returns the versionId of the current version of the resource, None if it doesn't exist
+    fn get_current_version(&self, pk: &[u8; 24]) -> Result<Option<u32>, RaError> {
+        let current = self.get_resource_by_pk(pk)?;
+        if let None = current {
+            return Ok(None);
+        }
+
+        let current = to_document(current.unwrap().as_ref())?;
+        Ok(Some(get_version(&current)))
+    }
+
     fn insert_search_param_batch(&self, prefix: &[u8; 4], mut data: Document, wb: &mut WriteBatch) -> Result<Document, RaError> {
         let res_id = Ksuid::generate();
         data.insert("id", Bson::from(res_id.to_base62()));
@@ -305,7 +466,7 @@ impl Barn {
         }
 
         let mut meta = meta.unwrap().as_document_mut().unwrap();
-        meta.insert("versionId", Bson::from(1));
+        meta.insert("versionId", Bson::from("1"));
         // this has to be inserted as a string otherwise when serialized to JSON
         // dates are formatted in extended-JSON format
         meta.insert("lastUpdated", Bson::from(Utc::now().format(bson_utils::DATE_FORMAT).to_string()));
@@ -319,13 +480,30 @@ impl Barn {
         Ok(data)
     }
 
+    /// returns the number of the current resources of the given type, this is the upper bound of the
+    /// number of resources matching any search and is used as the estimated total
+    pub fn count_resources(&self, rd: &ResourceDef) -> usize {
+        self.new_resource_key_iter(rd)
+            .take_while(|(k, _)| k.starts_with(&rd.hash))
+            // only the primary keys of resources are 24 bytes long
+            .filter(|(k, _)| k.len() == 24)
+            .count()
+    }
+
+    /// iterates over the current versions of all the resources of the given type
+    pub fn new_resource_key_iter<'d>(&'d self, rd: &'d ResourceDef) -> DBIterator<'d> {
+        self.db.prefix_iterator(&rd.hash)
+    }
+
     pub fn new_index_iter<'d>(&'d self, search_param_hash: &'d [u8]) -> DBIterator<'d> {
         let cf = self.db.cf_handle(CF_INDEX).unwrap();
         self.db.prefix_iterator_cf(cf, search_param_hash)
         //IndexIterator{prefix:search_param_hash, inner}
     }
 
-    pub fn resolve(&self, relative_url: &str, sd: &SchemaDef) -> Result<Vec<u8>, EvalError> {
+    /// reads the resource referred by the relative URL, the staged resources, if present,
+    /// take precedence over the ones stored in the database
+    pub fn resolve(&self, relative_url: &str, sd: &SchemaDef, staged: Option<&StagedResources>) -> Result<Vec<u8>, EvalError> {
         let mut parts = relative_url.splitn(2, "/");
         let res_name = parts.next();
         if let None = res_name {
@@ -343,8 +521,8 @@ impl Barn {
         }
 
         let res_id = res_id.unwrap();
-        let id = Ksuid::from_base62(res_id);
-        if let Err(e) = id {
+        let id = to_ksuid(res_id);
+        if let None = id {
             return Err(EvalError::new(format!("invalid resource ID {} in URL {}", res_id, relative_url)));
         }
 
@@ -352,6 +530,10 @@ impl Barn {
         pk[..4].copy_from_slice(&rd.unwrap().hash);
         pk[4..].copy_from_slice(id.unwrap().as_bytes());
 
+        if let Some(data) = staged.and_then(|s| s.get(&pk)) {
+            return Ok(data.clone());
+        }
+
         let res_data = self.db.get(pk);
         if let Err(e) = res_data {
             return Err(EvalError::new(format!("couldn't resolve the relative URL {} ({})", relative_url, e.to_string())));
@@ -366,6 +548,90 @@ impl Barn {
     }
 }
 
+/// maps the logical ID of a resource to the Ksuid used in the keys of the resource, its history
+/// and the index rows. IDs generated by the server are Ksuids and are used as is, any other valid
+/// FHIR ID (e.g `example` or a UUID) is mapped to a Ksuid with a zero timestamp and the name based
+/// UUID (v5) of the ID as the payload. The mapping is deterministic, hence references to arbitrary
+/// IDs can be indexed and resolved without looking up any other record.
+/// None is returned if the given value is not a valid FHIR ID
+pub fn to_ksuid(id: &str) -> Option<Ksuid> {
+    if let Ok(ksid) = Ksuid::from_base62(id) {
+        // the string form must remain the same for using the Ksuid as is
+        if ksid.to_base62() == id {
+            return Some(ksid);
+        }
+    }
+
+    if !FHIR_ID.is_match(id) {
+        return None;
+    }
+
+    let uuid = Uuid::new_v5(&Uuid::NAMESPACE_OID, id.as_bytes());
+    Some(Ksuid::new(0, *uuid.as_bytes()))
+}
+
+pub(crate) fn parse_res_id(rd: &ResourceDef, id: &str) -> Result<Ksuid, RaError> {
+    match to_ksuid(id) {
+        Some(ksid) => Ok(ksid),
+        None => Err(RaError::not_found(format!("{}/{} not found", &rd.name, id)))
+    }
+}
+
+impl VersionChecks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, pk: &[u8; 24], version: Option<u32>) {
+        self.versions.push((*pk, version));
+    }
+}
+
+/// reads the versionId from the meta of the resource, versions start from 1
+fn get_version(doc: &Document) -> u32 {
+    let version = bson_utils::get_int(doc, "meta.versionId");
+    if version < 1 { 1 } else { version as u32 }
+}
+
+/// checks the version given in If-Match header against the current version of the resource
+fn check_version(rd: &ResourceDef, id: &str, current_version: u32, if_match: Option<u32>) -> Result<(), RaError> {
+    if let Some(expected) = if_match {
+        if expected != current_version {
+            return Err(RaError::precondition_failed(IssueType::Conflict, format!("version {} doesn't match with the current version {} of {}/{}", expected, current_version, &rd.name, id)));
+        }
+    }
+
+    Ok(())
+}
+
+fn new_last_updated_key(pk: &[u8; 24]) -> [u8; 28] {
+    let mut key = [0; 28];
+    key[..4].copy_from_slice(&*LAST_UPDATED_KEY_PREFIX);
+    key[4..].copy_from_slice(pk);
+    key
+}
+
+/// checks whether the given version of a resource marks its deletion
+pub(crate) fn is_tombstone(doc: &Document) -> bool {
+    if let Ok(deleted) = doc.get_bool(TOMBSTONE_ATTR) {
+        return deleted;
+    }
+
+    false
+}
+
+pub(crate) fn to_document(data: &[u8]) -> Result<Document, RaError> {
+    let mut c = Cursor::new(data);
+    let doc = Document::from_reader(&mut c);
+    if let Err(e) = doc {
+        let msg = format!("error while deserializing the document data fetched from database ({})", e.to_string());
+        warn!("{}", &msg);
+        return Err(RaError::DbError(msg));
+    }
+
+    Ok(doc.unwrap())
+}
+
 impl Iterator for ResourceIterator<'_> {
     type Item = Document;
 
@@ -423,10 +689,25 @@ mod tests {
     use std::fs::File;
 
     use crate::res_schema::parse_res_def;
-    use crate::utils::test_utils::{parse_expression, read_patient, to_docbuf};
+    use crate::utils::test_utils::{parse_expression, read_patient, TestContainer, to_docbuf};
 
     use super::*;
 
+    #[test]
+    fn test_to_ksuid() {
+        let ksid = Ksuid::generate();
+        assert_eq!(ksid.as_bytes(), to_ksuid(&ksid.to_base62()).unwrap().as_bytes());
+
+        let example = to_ksuid("example").unwrap();
+        assert_eq!(example.as_bytes(), to_ksuid("example").unwrap().as_bytes());
+        assert_ne!(example.as_bytes(), to_ksuid("Example").unwrap().as_bytes());
+        assert!(to_ksuid("6f1f9a2c-7c1e-4bd6-8d6a-1c0d2f7a9b3e").is_some());
+
+        assert!(to_ksuid("").is_none());
+        assert!(to_ksuid("a/b").is_none());
+        assert!(to_ksuid(&"a".repeat(65)).is_none());
+    }
+
     #[test]
     fn test_search() -> Result<(), anyhow::Error> {
         let path = PathBuf::from("/tmp/testdb1");
@@ -462,4 +743,32 @@ mod tests {
         std::fs::remove_dir_all(&path);
         Ok(())
     }
+
+    #[test]
+    fn test_save_batch_with_concurrent_update() -> Result<(), anyhow::Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let sd = &api_base.schema;
+        let rd = sd.get_res_def_by_name("Patient")?;
+        let data = bson::to_document(&read_patient())?;
+        let doc = api_base.db.insert(rd, data, sd, false)?;
+        let id = doc.get_str("id")?;
+        let ksid = Ksuid::from_base62(id)?;
+
+        // prepare an update, and save it only after another update of the same resource
+        let mut wb = WriteBatch::default();
+        let mut checks = VersionChecks::new();
+        api_base.db.update_batch(&ksid, rd, doc.clone(), &mut wb, &mut checks, sd, true, false, None, None)?;
+        api_base.db.update(rd, id, doc.clone(), sd, true, false, Some(1))?;
+        let result = api_base.db.save_batch(wb, &checks);
+        assert!(matches!(result, Err(RaError::Custom{code: 409, ..})));
+        assert_eq!("2", api_base.db.read(rd, id)?.get_document("meta")?.get_str("versionId")?);
+
+        let mut wb = WriteBatch::default();
+        let mut checks = VersionChecks::new();
+        api_base.db.delete_batch(&ksid, rd, &mut wb, &mut checks, sd, true, None)?;
+        api_base.db.save_batch(wb, &checks)?;
+        assert!(api_base.db.get_resource_by_pk(&rd.new_id(ksid.as_bytes()))?.is_none());
+        Ok(())
+    }
 }
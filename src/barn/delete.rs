@@ -0,0 +1,116 @@
+use bson::{Bson, doc, Document};
+use ksuid::Ksuid;
+use log::debug;
+use rocksdb::WriteBatch;
+use crate::barn::{Barn, CF_INDEX, check_version, get_version, parse_res_id, to_document, TOMBSTONE_ATTR, VersionChecks};
+use crate::barn::insert::set_id_and_meta;
+use crate::errors::{IssueType, RaError};
+use crate::res_schema::SchemaDef;
+use crate::ResourceDef;
+
+impl Barn {
+    /// deletes the resource with the given ID and returns the tombstone that was placed in the history.
+    /// None is returned if the resource was already deleted
+    pub fn delete(&self, rd: &ResourceDef, id: &str, sd: &SchemaDef, keep_history: bool, if_match: Option<u32>) -> Result<Option<Document>, RaError> {
+        let ksid = parse_res_id(rd, id)?;
+        let mut wb = WriteBatch::default();
+        let mut checks = VersionChecks::new();
+        let tombstone = self.delete_batch(&ksid, rd, &mut wb, &mut checks, sd, keep_history, if_match)?;
+        if let None = tombstone {
+            return Ok(None);
+        }
+
+        self.save_batch(wb, &checks)?;
+        Ok(tombstone)
+    }
+
+    /// adds the changes of the deletion to the given batch and the deleted version to the checks
+    pub fn delete_batch(&self, ksid: &Ksuid, rd: &ResourceDef, wb: &mut WriteBatch, checks: &mut VersionChecks, sd: &SchemaDef, keep_history: bool, if_match: Option<u32>) -> Result<Option<Document>, RaError> {
+        let pk = rd.new_id(ksid.as_bytes());
+        let res_id = ksid.to_base62();
+        let current = self.get_resource_by_pk(&pk)?;
+        if let None = current {
+            let latest = self.get_latest_history_entry(rd, ksid.as_bytes())?;
+            if let Some(_) = latest {
+                if let Some(_) = if_match {
+                    return Err(RaError::precondition_failed(IssueType::Conflict, format!("{}/{} was already deleted", &rd.name, &res_id)));
+                }
+                debug!("{}/{} was already deleted", &rd.name, &res_id);
+                return Ok(None);
+            }
+            return Err(RaError::not_found(format!("{}/{} not found", &rd.name, &res_id)));
+        }
+
+        let current = current.unwrap().to_vec();
+        let current_doc = to_document(&current)?;
+        let res_id = current_doc.get_str("id").map_or(res_id, |id| id.to_string());
+        let current_version = get_version(&current_doc);
+        check_version(rd, &res_id, current_version, if_match)?;
+        checks.add(&pk, Some(current_version));
+
+        debug!("deleting {}/{} at version {}", &rd.name, &res_id, current_version);
+        if keep_history {
+            let history_pk = rd.new_history_version_id(ksid.as_bytes(), current_version);
+            wb.put(&history_pk, current.as_slice());
+        }
+
+        // the tombstone is always stored, irrespective of the versioning policy, to differentiate
+        // a deleted resource from one that never existed
+        let mut tombstone = doc! {"resourceType": rd.name.as_str()};
+        set_id_and_meta(&mut tombstone, res_id, current_version + 1);
+        tombstone.insert(TOMBSTONE_ATTR, Bson::Boolean(true));
+        let mut vec_bytes = Vec::new();
+        tombstone.to_writer(&mut vec_bytes)?;
+        let tombstone_pk = rd.new_history_version_id(ksid.as_bytes(), current_version + 1);
+        wb.put(&tombstone_pk, vec_bytes.as_slice());
+        self.log_change(wb, &pk, &tombstone);
+
+        wb.delete(&pk);
+        let rows = self.gen_index_rows(&pk, &current, rd, sd, None)?;
+        let cf = self.db.cf_handle(CF_INDEX).unwrap();
+        for (k, _) in rows {
+            wb.delete_cf(cf, k.as_slice());
+        }
+
+        Ok(Some(tombstone))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::barn::is_tombstone;
+    use crate::utils::test_utils::{read_patient, TestContainer};
+    use super::*;
+
+    #[test]
+    fn test_delete_removes_index_rows() -> Result<(), anyhow::Error> {
+        let tc = TestContainer::new();
+        let api_base = tc.setup_api_base_with_example_patient();
+        let sd = &api_base.schema;
+        let rd = sd.get_res_def_by_name("Patient")?;
+        let data = bson::to_document(&read_patient())?;
+        let doc = api_base.db.insert(rd, data, sd, false)?;
+        let id = doc.get_str("id")?;
+        let ksid = Ksuid::from_base62(id)?;
+        let pk = rd.new_id(ksid.as_bytes());
+
+        let mut vec_bytes = Vec::new();
+        doc.to_writer(&mut vec_bytes)?;
+        let rows = api_base.db.gen_index_rows(&pk, &vec_bytes, rd, sd, None)?;
+        assert!(!rows.is_empty());
+
+        let tombstone = api_base.db.delete(rd, id, sd, true, None)?.unwrap();
+        assert_eq!("2", tombstone.get_document("meta")?.get_str("versionId")?);
+
+        let db = &api_base.db.db;
+        let cf = db.cf_handle(CF_INDEX).unwrap();
+        for (k, _) in rows {
+            assert!(db.get_cf(cf, &k)?.is_none());
+        }
+        assert!(api_base.db.get_resource_by_pk(&pk)?.is_none());
+
+        let latest = api_base.db.get_latest_history_entry(rd, ksid.as_bytes())?.unwrap();
+        assert!(is_tombstone(&latest));
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+use std::convert::TryInto;
+use bson::Document;
+use chrono::{DateTime, Utc};
+use rocksdb::{Direction, IteratorMode};
+use crate::barn::{Barn, CHANGELOG_KEY_PREFIX, is_tombstone, parse_res_id, to_document};
+use crate::errors::RaError;
+use crate::res_schema::SchemaDef;
+use crate::ResourceDef;
+use crate::utils::bson_utils;
+
+impl Barn {
+    /// returns the versions of the resource with the given ID, most recent version first
+    pub fn history_instance(&self, rd: &ResourceDef, id: &str, since: Option<DateTime<Utc>>, at: Option<DateTime<Utc>>, count: usize) -> Result<Vec<Document>, RaError> {
+        let ksid = parse_res_id(rd, id)?;
+        let pk = rd.new_id(ksid.as_bytes());
+        let mut versions = Vec::new();
+        let current = self.get_resource_by_pk(&pk)?;
+        if let Some(current) = current {
+            versions.push(to_document(current.as_ref())?);
+        }
+
+        let prefix = rd.new_history_id(ksid.as_bytes());
+        let from = rd.new_history_version_id(ksid.as_bytes(), u32::MAX);
+        let itr = self.db.iterator(IteratorMode::From(&from, Direction::Reverse));
+        for (k, v) in itr {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            versions.push(to_document(v.as_ref())?);
+        }
+
+        if versions.is_empty() {
+            return Err(RaError::not_found(format!("{}/{} not found", &rd.name, id)));
+        }
+
+        let mut result = Vec::new();
+        // lastUpdated of the next (more recent) version
+        let mut next_updated: Option<DateTime<Utc>> = None;
+        for doc in versions {
+            let last_updated = bson_utils::get_time(&doc, "meta.lastUpdated");
+            if let Some(since) = since {
+                if last_updated < Some(since) {
+                    break;
+                }
+            }
+
+            if let Some(at) = at {
+                let superseded = next_updated.is_some() && next_updated <= Some(at);
+                next_updated = last_updated;
+                // only the version that was current at the given instant is included
+                if superseded || last_updated > Some(at) || is_tombstone(&doc) {
+                    continue;
+                }
+            }
+
+            result.push(doc);
+            if result.len() >= count {
+                break;
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// returns the versions of all resources of the given type, most recent first
+    pub fn history_type(&self, rd: &ResourceDef, since: Option<DateTime<Utc>>, at: Option<DateTime<Utc>>, count: usize, sd: &SchemaDef) -> Result<Vec<Document>, RaError> {
+        self.history_from_changelog(Some(rd), since, at, count, sd)
+    }
+
+    /// returns the versions of all resources, most recent first
+    pub fn history_system(&self, since: Option<DateTime<Utc>>, at: Option<DateTime<Utc>>, count: usize, sd: &SchemaDef) -> Result<Vec<Document>, RaError> {
+        self.history_from_changelog(None, since, at, count, sd)
+    }
+
+    /// walks the changelog backwards from the most recent change, stops as soon as
+    /// the changes older than the `since` instant are reached
+    fn history_from_changelog(&self, rd: Option<&ResourceDef>, since: Option<DateTime<Utc>>, at: Option<DateTime<Utc>>, count: usize, sd: &SchemaDef) -> Result<Vec<Document>, RaError> {
+        let prefix = &*CHANGELOG_KEY_PREFIX;
+        let mut from = [0xFF; 40];
+        from[..4].copy_from_slice(prefix);
+        let since = since.map(|t| t.timestamp_millis() as u64);
+        let at = at.map(|t| t.timestamp_millis() as u64);
+
+        let mut result = Vec::new();
+        let mut seen = HashSet::new();
+        let itr = self.db.iterator(IteratorMode::From(&from, Direction::Reverse));
+        for (k, _) in itr {
+            if !k.starts_with(prefix) {
+                break;
+            }
+
+            let millis = u64::from_be_bytes(k[4..12].try_into().unwrap());
+            if let Some(since) = since {
+                if millis < since {
+                    break;
+                }
+            }
+
+            let pk = &k[12..36];
+            if let Some(rd) = rd {
+                if &pk[..4] != &rd.hash {
+                    continue;
+                }
+            }
+
+            if let Some(at) = at {
+                // the first entry found before the given instant is the version that was current at that time
+                if millis > at || !seen.insert(pk.to_vec()) {
+                    continue;
+                }
+            }
+
+            let version = u32::from_be_bytes(k[36..40].try_into().unwrap());
+            let res_rd = sd.get_res_def_by_hash(&pk[..4])?;
+            let doc = self.get_version(res_rd, &pk[4..], version)?;
+            if let Some(doc) = doc {
+                if at.is_some() && is_tombstone(&doc) {
+                    continue;
+                }
+                result.push(doc);
+                if result.len() >= count {
+                    break;
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
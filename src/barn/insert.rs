@@ -7,7 +7,7 @@ use ksuid::Ksuid;
 use log::{debug, trace};
 use rawbson::elem::Element;
 use rocksdb::WriteBatch;
-use crate::barn::{Barn, CF_INDEX, ResolvableContext};
+use crate::barn::{Barn, CF_INDEX, ResolvableContext, StagedResources, to_ksuid};
 use crate::dtypes::DataType;
 use crate::errors::{EvalError, RaError};
 use crate::rapath::element_utils;
@@ -19,88 +19,123 @@ use crate::rapath::stypes::{SystemString, SystemType};
 use crate::res_schema::{SchemaDef, SearchParamDef, SearchParamExpr};
 use crate::ResourceDef;
 use crate::search::SearchParamType;
-use crate::utils::bson_utils;
+use crate::utils::{bson_utils, date_utils, ucum};
 use crate::utils::norm_utils::remove_diacritics_and_multi_spaces;
 
 impl Barn {
     pub fn insert_batch(&self, ksid: &Ksuid, res_def: &ResourceDef, mut data: Document, wb: &mut WriteBatch, sd: &SchemaDef, skip_indexing: bool) -> Result<(Document, Vec<u8>, [u8; 24]), RaError> {
         let res_id = ksid.to_base62();
         debug!("inserting a {} with ID {}", &res_def.name, &res_id);
-        data.insert("id", Bson::from(res_id));
-
-        // update metadata
-        let mut meta = data.get_mut("meta");
-        if let None = meta {
-            data.insert("meta", bson!({}));
-            meta = data.get_mut("meta");
-        }
-        // TODO is the below check needed??
-        // else if let Some(m) = meta {
-        //     if m.element_type() != ElementType::EmbeddedDocument {
-        //
-        //     }
-        // }
-        let mut meta = meta.unwrap().as_document_mut().unwrap();
-        meta.insert("versionId", Bson::from(1));
-        // this has to be inserted as a string otherwise when serialized to JSON
-        // dates are formatted in extended-JSON format
-        meta.insert("lastUpdated", Bson::from(Utc::now().format(bson_utils::DATE_FORMAT).to_string()));
-
-        // TODO move this block to update and replace calls
-        // check version history
-        // let history_pk = res_def.new_history_prefix_id(ksid.as_bytes());
-        // let history_count_rec = self.db.get(&history_pk);
-        // let mut history_count = 1; // history number always points to the current version (and it always starts with 1)
-        // if history_count_rec.is_ok() {
-        //     let history_count_rec = history_count_rec.unwrap().unwrap();
-        //     history_count = utils::u32_from_le_bytes(history_count_rec.as_bytes());
-        // }
+        set_id_and_meta(&mut data, res_id, 1);
 
         let mut vec_bytes = Vec::new();
         data.to_writer(&mut vec_bytes);
 
         let pk = res_def.new_id(ksid.as_bytes());
         wb.put(&pk, vec_bytes.as_slice());
+        self.log_change(wb, &pk, &data);
         if !skip_indexing {
-            self.index_searchparams(wb, &pk, &vec_bytes, res_def, sd)?;
+            self.index_searchparams(wb, &pk, &vec_bytes, res_def, sd, None)?;
         }
 
         Ok((data, vec_bytes, pk))
     }
 
-    pub fn index_searchparams(&self, wb: &mut WriteBatch, pk: &[u8; 24], res_data: &Vec<u8>, rd: &ResourceDef, sd: &SchemaDef) -> Result<(), RaError> {
-        let base = Element::new(ElementType::EmbeddedDocument, res_data.as_ref());
+    pub fn index_searchparams(&self, wb: &mut WriteBatch, pk: &[u8; 24], res_data: &Vec<u8>, rd: &ResourceDef, sd: &SchemaDef, staged: Option<&StagedResources>) -> Result<(), RaError> {
+        let rows = self.gen_index_rows(pk, res_data, rd, sd, staged)?;
+        let cf = self.db.cf_handle(CF_INDEX).unwrap();
+        for (k, v) in rows {
+            wb.put_cf(cf, k.as_slice(), v.as_slice());
+        }
+
+        Ok(())
+    }
+
+    /// evaluates all the search parameters of the resource and returns the index rows,
+    /// references are resolved from the staged resources before looking in the database
+    pub fn gen_index_rows(&self, pk: &[u8; 24], res_data: &[u8], rd: &ResourceDef, sd: &SchemaDef, staged: Option<&StagedResources>) -> Result<Vec<(Vec<u8>, Vec<u8>)>, RaError> {
+        let mut index_rows = Vec::new();
+        let base = Element::new(ElementType::EmbeddedDocument, res_data);
         let base = Rc::new(SystemType::Element(base));
         let search_params = sd.get_search_params_of(&rd.name);
         if let None = search_params {
             debug!("no search parameters found for the resource {}", &rd.name);
-            return Ok(());
+            return Ok(index_rows);
         }
         let search_params = search_params.unwrap();
         //println!("{:?}", search_params.iter().map(|e| e.0.to_string()).collect::<Vec<String>>());
-        let cf = self.db.cf_handle(CF_INDEX).unwrap();
-        let wrapped_sd = Some(sd);
         for (code, param_id) in search_params {
             let spd = sd.get_search_param(*param_id).unwrap();
             let expr = spd.expressions.get(&rd.name);
             let expr = expr.unwrap().as_ref().unwrap();
             //debug!("evaluating expression {} of search param {}", expr.expr, code);
-            let tokens = scan_tokens(expr.expr.as_str()).unwrap(); // the expression was already validated at the time of building schema
-            let ast = parse_with_schema(tokens, wrapped_sd).unwrap();
-            let ctx = ResolvableContext::new(Rc::clone(&base), self, sd);
-            let result = eval(&ctx, &ast, Rc::clone(&base))?;
-
-            let mut rows: Vec<Option<(Vec<u8>, Vec<u8>)>> = Vec::new();
-            format_index_rows(result, spd, expr, sd, pk, &mut rows)?;
-            for row in rows {
-                if let Some((k, v)) = row {
-                    wb.put_cf(cf, k.as_slice(), v.as_slice());
-                }
+            let rows = self.eval_search_param(&base, pk, spd, expr, sd, staged)?;
+            if spd.param_type == SearchParamType::Reference {
+                add_rev_ref_rows(&rows, pk, rd, sd, &mut index_rows)?;
             }
+            index_rows.extend(rows);
         }
 
-        Ok(())
+        Ok(index_rows)
+    }
+
+    /// returns the index rows of only the given search parameter
+    pub fn gen_search_param_rows(&self, pk: &[u8; 24], res_data: &[u8], spd: &SearchParamDef, expr: &SearchParamExpr, sd: &SchemaDef) -> Result<Vec<(Vec<u8>, Vec<u8>)>, RaError> {
+        let base = Element::new(ElementType::EmbeddedDocument, res_data);
+        let base = Rc::new(SystemType::Element(base));
+        self.eval_search_param(&base, pk, spd, expr, sd, None)
+    }
+
+    fn eval_search_param(&self, base: &Rc<SystemType>, pk: &[u8; 24], spd: &SearchParamDef, expr: &SearchParamExpr, sd: &SchemaDef, staged: Option<&StagedResources>) -> Result<Vec<(Vec<u8>, Vec<u8>)>, RaError> {
+        let tokens = scan_tokens(expr.expr.as_str()).unwrap(); // the expression was already validated at the time of building schema
+        let ast = parse_with_schema(tokens, Some(sd)).unwrap();
+        let ctx = ResolvableContext::with_staged(Rc::clone(base), self, sd, staged);
+        let result = eval(&ctx, &ast, Rc::clone(base))?;
+
+        let mut rows: Vec<Option<(Vec<u8>, Vec<u8>)>> = Vec::new();
+        format_index_rows(result, spd, expr, sd, pk, &mut rows)?;
+        Ok(rows.into_iter().flatten().collect())
+    }
+}
+
+/// adds a row for each resource referred by the given reference index rows, these rows
+/// are used for finding all the referrals of a resource irrespective of the search parameter (e.g _revinclude=*)
+fn add_rev_ref_rows(ref_rows: &[(Vec<u8>, Vec<u8>)], pk: &[u8; 24], rd: &ResourceDef, sd: &SchemaDef, index_rows: &mut Vec<(Vec<u8>, Vec<u8>)>) -> Result<(), RaError> {
+    for (k, _) in ref_rows {
+        // <search-param-hash><value-flag><target-type-hash><target-id><pk>
+        if k.len() != 53 || k[4] == 0 {
+            continue;
+        }
+        let to = sd.get_res_def_by_hash(&k[5..9])?;
+        let rev_id = rd.new_ref_rev_id(&k[9..29], to, &pk[4..]);
+        index_rows.push((rev_id.to_vec(), Vec::new()));
+    }
+
+    Ok(())
+}
+
+/// sets the given ID, versionId and lastUpdated time on the resource
+pub(crate) fn set_id_and_meta(data: &mut Document, res_id: String, version: u32) {
+    data.insert("id", Bson::from(res_id));
+
+    // update metadata
+    let mut meta = data.get_mut("meta");
+    if let None = meta {
+        data.insert("meta", bson!({}));
+        meta = data.get_mut("meta");
     }
+    // TODO is the below check needed??
+    // else if let Some(m) = meta {
+    //     if m.element_type() != ElementType::EmbeddedDocument {
+    //
+    //     }
+    // }
+    let mut meta = meta.unwrap().as_document_mut().unwrap();
+    // versionId is of type id and hence it must be a string
+    meta.insert("versionId", Bson::from(version.to_string()));
+    // this has to be inserted as a string otherwise when serialized to JSON
+    // dates are formatted in extended-JSON format
+    meta.insert("lastUpdated", Bson::from(Utc::now().format(bson_utils::DATE_FORMAT).to_string()));
 }
 
 fn format_index_rows(expr_result: Rc<SystemType>, spd: &SearchParamDef, expr: &SearchParamExpr, sd: &SchemaDef, pk: &[u8; 24], rows: &mut Vec<Option<(Vec<u8>, Vec<u8>)>>) -> Result<(), RaError> {
@@ -170,15 +205,54 @@ fn format_index_row(expr_result: Rc<SystemType>, spd: &SearchParamDef, expr: &Se
             }
         },
         SearchParamType::Date => {
-            if let SystemType::DateTime(sd) = expr_result {
-                key.push(1);
-                key.extend_from_slice(&sd.millis().to_le_bytes());
+            // the value is stored as the range it covers, e.g 1974 covers the whole year
+            let range = match expr_result {
+                SystemType::String(s) => Some(date_utils::to_date_range(s.as_str())?),
+                SystemType::DateTime(sd) => Some((sd.millis(), sd.millis())),
+                SystemType::Element(e) => date_utils::element_to_date_range(e)?,
+                _ => None
+            };
+            match range {
+                Some((low, high)) => {
+                    key.push(1);
+                    key.extend_from_slice(&low.to_le_bytes());
+                    key.extend_from_slice(&high.to_le_bytes());
+                },
+                None => key.push(0)
+            }
+        },
+        SearchParamType::Quantity => {
+            let quantity = match expr_result {
+                SystemType::Quantity(sq) => Some((sq.value(), sq.system(), Some(sq.code()))),
+                SystemType::Element(e) => element_utils::gather_quantity(e)?,
+                _ => None
+            };
+            match quantity {
+                Some((val, system, code)) => {
+                    // UCUM quantities are stored in the canonical unit so that 5.4 mg and 5400 ug are found alike
+                    let mut canonical = None;
+                    if let Some(c) = code {
+                        if system.is_none() || system == Some(ucum::UCUM_SYSTEM) {
+                            canonical = ucum::canonicalize(val, c);
+                        }
+                    }
+                    let (key_val, code) = match canonical {
+                        Some((v, c)) => (v, Some(c)),
+                        None => (val, code)
+                    };
+
+                    key.push(1);
+                    key.extend_from_slice(&key_val.to_le_bytes());
+                    for s in [system, code] {
+                        let data = s.unwrap_or_default().as_bytes();
+                        key.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                        key.extend_from_slice(data);
+                    }
+                    value.extend_from_slice(&val.to_le_bytes()); // the value in the original unit
+                },
+                None => key.push(0)
             }
         },
-        // SearchParamType::Quantity => {
-        //     if let SystemType::Quantity(sq) = expr_result {
-        //     }
-        // },
         SearchParamType::Token => {
             if let SystemType::Element(e) = expr_result {
                 let (system, code) = gather_system_and_code(e)?;
@@ -227,10 +301,14 @@ fn format_index_row(expr_result: Rc<SystemType>, spd: &SearchParamDef, expr: &Se
                 }
             }
         },
+        SearchParamType::Uri => {
+            if let SystemType::String(s) = expr_result {
+                key.push(1);
+                key.extend_from_slice(s.as_str().as_bytes()); // URIs are case sensitive, stored as is
+            }
+        },
         // SearchParamType::Composite => {
         // },
-        // SearchParamType::Uri => {
-        // },
         // SearchParamType::Speacial => {
         // }
         _ => {}
@@ -250,9 +328,9 @@ fn get_reference_val_from(el: &Element, sd: &SchemaDef) -> Result<Option<([u8; 2
                     // split again to extract version
                     let mut parts = parts[1].splitn(2, "|");
                     if let Some(res_id) = parts.next() {
-                        let to_id = Ksuid::from_base62(res_id);
-                        if let Err(e) = to_id {
-                            return Err(RaError::BadRequest(format!("invalid resource ID in reference {} ({})", target, e.to_string())));
+                        let to_id = to_ksuid(res_id);
+                        if let None = to_id {
+                            return Err(RaError::BadRequest(format!("invalid resource ID in reference {}", target)));
                         }
                         let to_id = to_id.unwrap();
                         let mut ref_id: [u8; 24] = [0; 24];
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use bson::Document;
+use ksuid::Ksuid;
+use log::debug;
+use rocksdb::WriteBatch;
+use crate::barn::{Barn, CF_INDEX, check_version, get_version, parse_res_id, StagedResources, to_document, VersionChecks};
+use crate::barn::insert::set_id_and_meta;
+use crate::errors::{IssueType, RaError};
+use crate::res_schema::SchemaDef;
+use crate::ResourceDef;
+use crate::utils::bson_utils;
+
+impl Barn {
+    /// updates the resource with the given ID, returns the updated resource and a flag
+    /// indicating whether the resource was created (only when update_create is true)
+    pub fn update(&self, rd: &ResourceDef, id: &str, data: Document, sd: &SchemaDef, keep_history: bool, update_create: bool, if_match: Option<u32>) -> Result<(Document, bool), RaError> {
+        let ksid = parse_res_id(rd, id)?;
+        let mut wb = WriteBatch::default();
+        let mut checks = VersionChecks::new();
+        let (doc, created) = self.update_batch(&ksid, rd, data, &mut wb, &mut checks, sd, keep_history, update_create, if_match, None)?;
+        self.save_batch(wb, &checks)?;
+        Ok((doc, created))
+    }
+
+    /// adds the changes of the update to the given batch and the version it is based on to the checks,
+    /// the staged resources are used for resolving the references while indexing
+    pub fn update_batch(&self, ksid: &Ksuid, rd: &ResourceDef, mut data: Document, wb: &mut WriteBatch, checks: &mut VersionChecks, sd: &SchemaDef, keep_history: bool, update_create: bool, if_match: Option<u32>, staged: Option<&StagedResources>) -> Result<(Document, bool), RaError> {
+        let pk = rd.new_id(ksid.as_bytes());
+        // the ID given by the client is retained, the Ksuid it maps to is only used in the keys
+        let res_id = match data.get_str("id") {
+            Ok(id) => id.to_string(),
+            Err(_) => ksid.to_base62()
+        };
+        let current = self.get_resource_by_pk(&pk)?;
+        if let None = current {
+            if !update_create {
+                return Err(RaError::not_found(format!("{}/{} not found", &rd.name, &res_id)));
+            }
+            if let Some(_) = if_match {
+                return Err(RaError::precondition_failed(IssueType::Conflict, format!("{}/{} doesn't exist", &rd.name, &res_id)));
+            }
+
+            // a deleted resource can be brought back to life, its version numbering continues from the tombstone
+            let latest = self.get_latest_history_entry(rd, ksid.as_bytes())?;
+            let latest_version = match latest {
+                Some(latest) => {
+                    let v = bson_utils::get_int(&latest, "meta.versionId");
+                    if v < 1 { 1 } else { v as u32 }
+                },
+                None => 0
+            };
+            checks.add(&pk, None);
+            debug!("creating {}/{} with version {}", &rd.name, &res_id, latest_version + 1);
+            set_id_and_meta(&mut data, res_id, latest_version + 1);
+            let mut vec_bytes = Vec::new();
+            data.to_writer(&mut vec_bytes)?;
+            wb.put(&pk, vec_bytes.as_slice());
+            self.log_change(wb, &pk, &data);
+            self.index_searchparams(wb, &pk, &vec_bytes, rd, sd, staged)?;
+            return Ok((data, true));
+        }
+
+        let current = current.unwrap().to_vec();
+        let current_doc = to_document(&current)?;
+        let current_version = get_version(&current_doc);
+        check_version(rd, &res_id, current_version, if_match)?;
+        checks.add(&pk, Some(current_version));
+        debug!("updating {}/{} to version {}", &rd.name, &res_id, current_version + 1);
+        set_id_and_meta(&mut data, res_id, current_version + 1);
+        let mut vec_bytes = Vec::new();
+        data.to_writer(&mut vec_bytes)?;
+        wb.put(&pk, vec_bytes.as_slice());
+        self.log_change(wb, &pk, &data);
+
+        if keep_history {
+            let history_pk = rd.new_history_version_id(ksid.as_bytes(), current_version);
+            wb.put(&history_pk, current.as_slice());
+        }
+
+        self.reindex(wb, &pk, &current, &vec_bytes, rd, sd, staged)?;
+
+        Ok((data, false))
+    }
+
+    /// updates only the index rows whose values differ between the old and new versions of the resource
+    fn reindex(&self, wb: &mut WriteBatch, pk: &[u8; 24], old_data: &[u8], new_data: &[u8], rd: &ResourceDef, sd: &SchemaDef, staged: Option<&StagedResources>) -> Result<(), RaError> {
+        let old_rows = self.gen_index_rows(pk, old_data, rd, sd, None)?;
+        let new_rows = self.gen_index_rows(pk, new_data, rd, sd, staged)?;
+        let mut old_rows: HashMap<Vec<u8>, Vec<u8>> = old_rows.into_iter().collect();
+
+        let cf = self.db.cf_handle(CF_INDEX).unwrap();
+        for (k, v) in new_rows {
+            let old_val = old_rows.remove(&k);
+            if let Some(old_val) = old_val {
+                if old_val == v {
+                    continue;
+                }
+            }
+            wb.put_cf(cf, k.as_slice(), v.as_slice());
+        }
+
+        // whatever remains is no longer present in the new version
+        for (k, _) in old_rows {
+            wb.delete_cf(cf, k.as_slice());
+        }
+
+        Ok(())
+    }
+}
@@ -4,49 +4,53 @@ use serde::{Serialize, Deserialize};
 pub struct Config {
     /// the base URL (this is the external facing URL from which requests can be proxied to the local URL listening at the localhost and the port)
     #[serde(rename = "baseUrl")]
-    base_url: String,
+    pub base_url: String,
 
     /// this is the actual URL where the server listens for requests
     #[serde(rename = "port")]
-    port: u16,
+    pub port: u16,
 
     /// list of supported ResourceTypes
     #[serde(rename = "supportedResTypes")]
-    supported_res_types: Vec<String>,
+    pub supported_res_types: Vec<String>,
 
-    versioning: Versioning,
+    pub versioning: Versioning,
     #[serde(rename = "readHistory")]
-    read_history: bool,
+    pub read_history: bool,
 
     #[serde(rename = "updateCreate")]
-    update_create: bool,
+    pub update_create: bool,
 
     #[serde(rename = "conditionalCreate")]
-    conditional_create: bool,
+    pub conditional_create: bool,
 
     #[serde(rename = "conditionalRead")]
-    conditional_read: bool,
+    pub conditional_read: bool,
 
     #[serde(rename = "conditionalUpdate")]
-    conditional_update: bool,
+    pub conditional_update: bool,
 
     #[serde(rename = "conditionalDelete")]
-    conditional_delete: bool,
+    pub conditional_delete: bool,
 
     #[serde(rename = "referencePolicy")]
-    reference_policy: ReferencePolicy,
+    pub reference_policy: ReferencePolicy,
 
     #[serde(rename = "searchInclude")]
-    search_include: bool,
+    pub search_include: bool,
 
     #[serde(rename = "searchRevInclude")]
-    search_rev_include: bool,
+    pub search_rev_include: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
 pub enum Versioning {
+    #[serde(rename = "no-version")]
     No_version,
+    #[serde(rename = "versioned")]
     Versioned,
+    #[serde(rename = "versioned-update")]
     Versioned_Update
 }
 
@@ -57,4 +61,35 @@ pub enum ReferencePolicy {
     Resolves,
     Enforced,
     Local
-}
\ No newline at end of file
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            base_url: String::from(""),
+            port: 7090,
+            supported_res_types: Vec::new(),
+            versioning: Versioning::Versioned,
+            read_history: true,
+            update_create: false,
+            conditional_create: false,
+            conditional_read: false,
+            conditional_update: false,
+            conditional_delete: false,
+            reference_policy: ReferencePolicy::Literal,
+            search_include: false,
+            search_rev_include: false
+        }
+    }
+}
+
+impl Versioning {
+    /// the code used in CapabilityStatement
+    pub fn code(&self) -> &'static str {
+        match self {
+            Versioning::No_version => "no-version",
+            Versioning::Versioned => "versioned",
+            Versioning::Versioned_Update => "versioned-update"
+        }
+    }
+}
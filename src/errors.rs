@@ -34,6 +34,52 @@ impl RaError {
     pub fn bad_req<S: AsRef<str>>(msg: S) -> Self {
         Self::BadRequest(String::from(msg.as_ref()))
     }
+
+    pub fn not_found<S: AsRef<str>>(msg: S) -> Self {
+        Self::NotFound(String::from(msg.as_ref()))
+    }
+
+    /// a precondition of the interaction was not met (HTTP 412)
+    pub fn precondition_failed<S: AsRef<str>>(code: IssueType, msg: S) -> Self {
+        let outcome = OperationOutcome::new_error(code, msg);
+        Self::Custom {code: 412, outcome}
+    }
+
+    /// converts the error into the HTTP status code and the OperationOutcome describing it
+    pub fn to_outcome(self) -> (u16, OperationOutcome) {
+        match self {
+            RaError::DbError(s) | RaError::SchemaParsingError(s) => {
+                (500, OperationOutcome::new_error(IssueType::Exception, s))
+            },
+            RaError::SchemaValidationError => {
+                (500, OperationOutcome::new_error(IssueType::Processing, "schema validation failed"))
+            },
+            RaError::SearchParamParsingError(s) => {
+                (500, OperationOutcome::new_error(IssueType::Processing, s))
+            },
+            RaError::BadRequest(s) => {
+                (400, OperationOutcome::new_error(IssueType::Processing, s))
+            },
+            RaError::NotFound(s) => {
+                (404, OperationOutcome::new_error(IssueType::Not_found, s))
+            },
+            RaError::Custom {code, outcome} => {
+                (code, outcome)
+            }
+        }
+    }
+
+    /// the resource existed once but was deleted (HTTP 410)
+    pub fn gone<S: AsRef<str>>(msg: S) -> Self {
+        let outcome = OperationOutcome::new_error(IssueType::Deleted, msg);
+        Self::Custom {code: 410, outcome}
+    }
+
+    /// the resource was modified by a concurrent request (HTTP 409)
+    pub fn conflict<S: AsRef<str>>(msg: S) -> Self {
+        let outcome = OperationOutcome::new_error(IssueType::Conflict, msg);
+        Self::Custom {code: 409, outcome}
+    }
 }
 
 #[derive(Debug)]
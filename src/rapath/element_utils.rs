@@ -410,6 +410,34 @@ pub fn gather_system_and_code<'i>(el: &'i Element) -> Result<(Option<&'i str>, O
     Ok((system, code))
 }
 
+/// returns the value, system and code of a Quantity element, the unit is used when the code is absent
+pub fn gather_quantity<'i>(el: &'i Element) -> Result<Option<(f64, Option<&'i str>, Option<&'i str>)>, EvalError> {
+    if el.element_type() != ElementType::EmbeddedDocument {
+        return Ok(None);
+    }
+
+    let doc = el.as_document()?;
+    let value = match doc.get("value")? {
+        Some(v) => {
+            match v.element_type() {
+                ElementType::Double => v.as_f64()?,
+                ElementType::Int32 => v.as_i32()? as f64,
+                ElementType::Int64 => v.as_i64()? as f64,
+                _ => return Ok(None)
+            }
+        },
+        None => return Ok(None)
+    };
+
+    let system = get_str_val(doc, "system");
+    let mut code = get_str_val(doc, "code");
+    if let None = code {
+        code = get_str_val(doc, "unit");
+    }
+
+    Ok(Some((value, system, code)))
+}
+
 fn get_str_val<'i>(doc: &'i Doc, name: &str) -> Option<&'i str> {
     let el = doc.get_str(name);
     if let Ok(el) = el {
@@ -1,5 +1,6 @@
 use std::borrow::Borrow;
 use std::rc::Rc;
+use rawbson::elem::ElementType;
 use serde_json::Value;
 use crate::errors::EvalError;
 use crate::rapath::EvalResult;
@@ -38,14 +39,22 @@ fn convert<'b>(st: Rc<SystemType<'b>>, type_name: &str) -> EvalResult<'b> {
                 if let None = val {
                     return Err(EvalError::from_str("missing value attribute in Quantity element"));
                 }
-                let unit = doc.get("unit")?;
+                let system = doc.get_str("system")?;
+                let code = doc.get_str("code")?;
+                // the code is used when the unit is absent
+                let unit = doc.get_str("unit")?.or(code);
                 if let None = unit {
                     return Err(EvalError::from_str("missing unit attribute in Quantity element"));
                 }
-                let val = val.unwrap().as_f64()?;
-                let unit = unit.unwrap().as_str()?;
+                let val = val.unwrap();
+                let val = match val.element_type() {
+                    ElementType::Int32 => val.as_i32()? as f64,
+                    ElementType::Int64 => val.as_i64()? as f64,
+                    _ => val.as_f64()?
+                };
+                let unit = unit.unwrap();
                 // TODO this copying needs to be eliminated, but that requires refactoring scanner and parser
-                let sq = SystemQuantity::new(val, String::from(unit));
+                let sq = SystemQuantity::new(val, String::from(unit)).with_system_and_code(system, code);
                 return Ok(Rc::new(SystemType::Quantity(sq)));
             }
             else {
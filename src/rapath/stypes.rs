@@ -206,7 +206,10 @@ pub struct SystemConstant {
 pub struct SystemQuantity {
     val: f64,
     unit: String,
-    cal_unit: bool
+    cal_unit: bool,
+    /// the system and code of a Quantity element, literals do not have these
+    system: Option<String>,
+    code: Option<String>
 }
 
 impl SystemQuantity {
@@ -218,7 +221,29 @@ impl SystemQuantity {
             // this makes it easy to compare in equals() method
             unit = String::from("second");
         }
-        SystemQuantity{val, unit, cal_unit}
+        SystemQuantity{val, unit, cal_unit, system: None, code: None}
+    }
+
+    pub fn with_system_and_code(mut self, system: Option<&str>, code: Option<&str>) -> Self {
+        self.system = system.map(String::from);
+        self.code = code.map(String::from);
+        self
+    }
+
+    #[inline]
+    pub fn value(&self) -> f64 {
+        self.val
+    }
+
+    #[inline]
+    pub fn system(&self) -> Option<&str> {
+        self.system.as_deref()
+    }
+
+    /// returns the code, or the unit if the code is absent
+    #[inline]
+    pub fn code(&self) -> &str {
+        self.code.as_deref().unwrap_or(self.unit.as_str())
     }
 
     pub fn equals<'b>(lhs: &SystemQuantity, rhs: &SystemQuantity) -> SystemType<'b> {
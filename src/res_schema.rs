@@ -596,6 +596,17 @@ impl ResourceDef {
         prefix_id(&self.history_hash, ksid)
     }
 
+    /// generates the key of a specific version of the resource in version history
+    /// <history-hash><ksuid><version-number(big endian)>, the version number is stored in
+    /// big endian format to keep all the versions of a resource sorted in ascending order
+    pub fn new_history_version_id(&self, ksid: &[u8], version: u32) -> [u8; 28] {
+        let mut tmp: [u8; 28] = [0; 28];
+        tmp[..24].copy_from_slice(&self.new_history_id(ksid));
+        tmp[24..].copy_from_slice(&version.to_be_bytes());
+
+        tmp
+    }
+
     /// (_include) Observation(O1) -> Patient(P1) : <Observation-ref-attribute-crc32-hash><Observation-id><Patient-type-crc32-hash>=<Patient-id>
     /// e.g <Observation_subject><O1><Patient><P1>
     pub fn new_ref_fwd_id<S: AsRef<str>>(&self, for_at_name: S, from_id: &[u8], to: &ResourceDef, to_id: &[u8]) -> [u8; 48] {
@@ -757,6 +768,18 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_history_id_generation() {
+        let s = parse_schema();
+        let patient = s.resources.get("Patient").unwrap();
+        let id = ksuid::Ksuid::generate();
+        let v1 = patient.new_history_version_id(id.as_bytes(), 1);
+        let v2 = patient.new_history_version_id(id.as_bytes(), 256);
+        assert_eq!(&patient.new_history_id(id.as_bytes()), &v1[..24]);
+        assert_eq!(&[0, 0, 0, 1], &v1[24..]);
+        assert!(v1 < v2);
+    }
+
     #[test]
     fn test_search_param_expr_parsing() -> Result<(), Error> {
         let s = parse_schema();
@@ -11,6 +11,8 @@ mod filter_parser;
 pub mod executor;
 pub mod filter_converter;
 pub mod index_scanners;
+pub mod include;
+pub mod sort;
 
 pub struct SearchExpr {
     name: String,
@@ -1,15 +1,15 @@
+use std::cmp::Ordering;
 use std::collections::VecDeque;
 use std::io::Cursor;
 use std::process::id;
 use std::rc::Rc;
 use bson::Document;
-use ksuid::Ksuid;
 use lazy_static::lazy_static;
 use log::debug;
 use regex::Regex;
-use crate::api::base::{OperationOutcome, RaResponse, SearchQuery};
+use crate::api::base::{OperationOutcome, RaResponse, SearchQuery, Summary, Total};
 use crate::api::bundle::{SearchEntry, SearchSet};
-use crate::barn::Barn;
+use crate::barn::{Barn, to_ksuid};
 use crate::res_schema::{SchemaDef, SearchParamDef, SearchParamExpr};
 use crate::ResourceDef;
 use crate::search::{Filter, Modifier, SearchParamType};
@@ -19,21 +19,62 @@ use crate::rapath::parser::parse;
 use crate::rapath::scanner::scan_tokens;
 use crate::search::ComparisonOperator;
 use crate::search::index_scanners::and_or::AndOrIndexScanner;
+use crate::search::index_scanners::date::DateIndexScanner;
 use crate::search::index_scanners::not::NotIndexScanner;
+use crate::search::index_scanners::number::NumberIndexScanner;
+use crate::search::index_scanners::quantity::QuantityIndexScanner;
 use crate::search::index_scanners::reference::{ChainedParam, ReferenceChainIndexScanner};
 use crate::search::index_scanners::string::StringIndexScanner;
 use crate::search::index_scanners::token::TokenIndexScanner;
+use crate::search::index_scanners::uri::UriIndexScanner;
+use crate::search::sort;
+use crate::search::sort::SortKey;
 
 lazy_static! {
     static ref HTTP_RE: Regex = Regex::new(r"(?i)^((http|https)://)").unwrap();
 }
 pub fn execute_search_query(filter: &Filter, sq: &SearchQuery, rd: &ResourceDef, db: &Barn, sd: &SchemaDef) -> Result<RaResponse, RaError> {
+    let mut ss = SearchSet::new();
+    // the estimate is read without scanning the index
+    if sq.summary == Summary::Count && sq.total == Total::Estimate {
+        ss.total = Some(db.count_resources(rd));
+        return Ok(RaResponse::SearchResult(ss));
+    }
+
     let mut idx = to_index_scanner(filter, rd, sd, db)?;
     let keys = idx.collect_all();
-    let mut ss = SearchSet::new();
-    let mut count = 0;
-    for (ref k, _) in keys {
-        let res = db.get_resource_by_pk(k)?;
+    // the matching keys are gathered for ordering the results, hence the total is
+    // accurate even when only an estimate was requested
+    if sq.total != Total::None || sq.summary == Summary::Count {
+        ss.total = Some(keys.len());
+    }
+    if sq.summary == Summary::Count {
+        return Ok(RaResponse::SearchResult(ss));
+    }
+
+    let sort_params = sort::parse_sort(sq.sort);
+    let keys = sort::sort_keys(keys, &sort_params, rd, sd, db)?;
+
+    let count = sq.count as usize;
+    let start = match sq.page {
+        // a page starts right after (or ends right before) the key in the token, this keeps
+        // the pages consistent even when resources are created or deleted while paging
+        Some(token) => {
+            let (forward, boundary) = decode_page_token(token, sort_params.len())?;
+            if forward {
+                keys.partition_point(|k| sort::compare(k, &boundary, &sort_params) != Ordering::Greater)
+            }
+            else {
+                keys.partition_point(|k| sort::compare(k, &boundary, &sort_params) == Ordering::Less).saturating_sub(count)
+            }
+        },
+        None => sq.offset as usize
+    };
+    let start = start.min(keys.len());
+    let end = (start + count).min(keys.len());
+
+    for k in &keys[start..end] {
+        let res = db.get_resource_by_pk(&k.pk)?;
         if let Some(res) = res {
             let mut cursor = Cursor::new(res.as_ref());
             let doc = Document::from_reader(&mut cursor);
@@ -43,18 +84,50 @@ pub fn execute_search_query(filter: &Filter, sq: &SearchQuery, rd: &ResourceDef,
                 return Err(RaError::Custom{code: 500, outcome: oo});
             }
             ss.add(doc.unwrap());
-            count += 1;
-            if count >= sq.count {
-                break;
-            }
+        }
+    }
+
+    if end > start {
+        if end < keys.len() {
+            ss.next_page = Some(encode_page_token(true, &keys[end - 1]));
+        }
+        if start > 0 {
+            ss.prev_page = Some(encode_page_token(false, &keys[start]));
         }
     }
     Ok(RaResponse::SearchResult(ss))
 }
 
+/// encodes the direction and the sort key of the resource at which the page starts as an opaque token
+fn encode_page_token(forward: bool, sk: &SortKey) -> String {
+    let mut token = vec![forward as u8];
+    token.extend_from_slice(&sort::encode_sort_key(sk));
+    base64::encode_config(&token, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_page_token(token: &str, num_sort_params: usize) -> Result<(bool, SortKey), RaError> {
+    let data = base64::decode_config(token, base64::URL_SAFE_NO_PAD);
+    if let Ok(data) = data {
+        if !data.is_empty() && data[0] < 2 {
+            if let Some(sk) = sort::decode_sort_key(&data[1..]) {
+                // the token must have been generated for the same sort order
+                if sk.values.len() == num_sort_params {
+                    return Ok((data[0] == 1, sk));
+                }
+            }
+        }
+    }
+
+    Err(RaError::bad_req(format!("invalid page token {}", token)))
+}
+
 pub fn to_index_scanner<'f, 'd: 'f>(filter: &'f Filter, rd: &'f ResourceDef, sd: &'f SchemaDef, db: &'d Barn) -> Result<Box<dyn IndexScanner<'f> + 'f>, EvalError> {
     match filter {
         Filter::SimpleFilter {identifier, value,  operator} => {
+            if identifier.starts_with("_has:") {
+                let has = parse_has_param(identifier, sd)?;
+                return create_reverse_chain_scanner(has, value, operator, rd, sd, db);
+            }
             let (name, modifier, path) = parse_attribute_name(identifier);
             return create_index_scanner(name, value, operator, modifier, path, rd, sd, db);
         },
@@ -113,11 +186,38 @@ pub fn create_index_scanner<'f>(name: &'f str, value: &'f str, operator: &'f Com
             let tmp = StringIndexScanner::new(value, itr, operator, &sp_expr.hash, modifier);
             idx_scanner = Box::new(tmp);
         },
-        SearchParamType::Token => {
+        SearchParamType::Date => {
             let itr = db.new_index_iter(&sp_expr.hash);
-            let tmp = TokenIndexScanner::new(value, itr, &sp_expr.hash, modifier);
+            let tmp = DateIndexScanner::new(value, itr, operator, &sp_expr.hash)?;
             idx_scanner = Box::new(tmp);
         },
+        SearchParamType::Number => {
+            let itr = db.new_index_iter(&sp_expr.hash);
+            let tmp = NumberIndexScanner::new(value, itr, operator, &sp_expr.hash)?;
+            idx_scanner = Box::new(tmp);
+        },
+        SearchParamType::Quantity => {
+            let itr = db.new_index_iter(&sp_expr.hash);
+            let tmp = QuantityIndexScanner::new(value, itr, operator, &sp_expr.hash)?;
+            idx_scanner = Box::new(tmp);
+        },
+        SearchParamType::Uri => {
+            let itr = db.new_index_iter(&sp_expr.hash);
+            let tmp = UriIndexScanner::new(value, itr, &sp_expr.hash, modifier)?;
+            idx_scanner = Box::new(tmp);
+        },
+        SearchParamType::Token => {
+            let itr = db.new_index_iter(&sp_expr.hash);
+            if modifier == Modifier::Not {
+                // resources that do not have the element must also match, hence the complement of the matches
+                let tmp = TokenIndexScanner::new(value, itr, &sp_expr.hash, Modifier::None);
+                idx_scanner = Box::new(NotIndexScanner::new(Box::new(tmp), rd, db));
+            }
+            else {
+                let tmp = TokenIndexScanner::new(value, itr, &sp_expr.hash, modifier);
+                idx_scanner = Box::new(tmp);
+            }
+        },
         SearchParamType::Reference => {
             let itr = db.new_index_iter(&sp_expr.hash);
             if modifier == Modifier::Identifier {
@@ -192,8 +292,8 @@ pub fn create_index_scanner<'f>(name: &'f str, value: &'f str, operator: &'f Com
             if let None = ref_id {
                 return Err(EvalError::new(format!("missing reference ID in reference {}", value)));
             }
-            let ref_id_val = Ksuid::from_base62(ref_id.unwrap());
-            if let Err(e) = ref_id_val {
+            let ref_id_val = to_ksuid(ref_id.unwrap());
+            if let None = ref_id_val {
                 return Err(EvalError::new(format!("invalid reference ID {}", ref_id.unwrap())));
             }
             let tmp = reference::new_reference_scanner(ref_id_val.unwrap(), ref_type_hash, itr, &sp_expr.hash, modifier);
@@ -207,6 +307,63 @@ pub fn create_index_scanner<'f>(name: &'f str, value: &'f str, operator: &'f Com
     return Ok(idx_scanner);
 }
 
+/// a parsed _has parameter e.g _has:Encounter:subject:_has:Observation:encounter:code
+pub struct HasParam<'a> {
+    /// the referring resources in the order of the chain, each along with its reference parameter
+    pub links: Vec<(&'a ResourceDef, &'a SearchParamExpr)>,
+    /// the search parameter, including its modifiers, of the last resource in the chain
+    pub param: &'a str
+}
+
+impl<'a> HasParam<'a> {
+    /// the resource on which the parameter at the end of the chain is evaluated
+    pub fn last_res_def(&self) -> &'a ResourceDef {
+        self.links.last().unwrap().0
+    }
+}
+
+/// parses the chain of a _has parameter and validates the resources and the reference parameters in it
+pub fn parse_has_param<'a>(name: &'a str, sd: &'a SchemaDef) -> Result<HasParam<'a>, EvalError> {
+    let mut links = Vec::new();
+    let mut param = name;
+    while let Some(chain) = param.strip_prefix("_has:") {
+        let parts: Vec<&str> = chain.splitn(3, ":").collect();
+        if parts.len() != 3 || parts.iter().any(|p| p.is_empty()) {
+            return Err(EvalError::new(format!("invalid _has parameter {}", name)));
+        }
+
+        let referring_rd = sd.resources.get(parts[0]).ok_or_else(|| EvalError::new(format!("unknown resourceType {} in _has parameter", parts[0])))?;
+        let (spd, sp_expr) = find_search_param_expr(parts[1], referring_rd, sd)?;
+        if spd.param_type != SearchParamType::Reference {
+            return Err(EvalError::new(format!("{} is not a reference search parameter of {}", parts[1], parts[0])));
+        }
+
+        links.push((referring_rd, sp_expr));
+        param = parts[2];
+    }
+
+    if links.is_empty() {
+        return Err(EvalError::new(format!("invalid _has parameter {}", name)));
+    }
+
+    Ok(HasParam{links, param})
+}
+
+/// creates a scanner for the _has parameter e.g _has:Observation:patient:code=1234-5 selects the resources
+/// referred by the patient parameter of the Observations having the code 1234-5
+fn create_reverse_chain_scanner<'f>(has: HasParam<'f>, value: &'f str, operator: &'f ComparisonOperator, rd: &'f ResourceDef, sd: &'f SchemaDef, db: &'f Barn) -> Result<Box<dyn IndexScanner<'f> + 'f>, EvalError> {
+    let (name, modifier, path) = parse_attribute_name(has.param);
+    let mut scanner = create_index_scanner(name, value, operator, modifier, path, has.last_res_def(), sd, db)?;
+
+    // walk back the chain, each link selects the resources referred by the ones selected after it
+    for (i, &(_, sp_expr)) in has.links.iter().enumerate().rev() {
+        let target = if i == 0 { rd } else { has.links[i - 1].0 };
+        scanner = Box::new(reference::new_reverse_chain_scanner(scanner, db, &sp_expr.hash, target.hash));
+    }
+
+    Ok(scanner)
+}
+
 fn parse_attribute_name(name: &str) -> (&str, Modifier, Option<&str>) {
     let mut parts = name.splitn(2, ".");
     let mut at_name = parts.next().unwrap();
@@ -3,10 +3,19 @@ use crate::errors::EvalError;
 use crate::res_schema::SchemaDef;
 use crate::ResourceDef;
 use crate::search::{ComparisonOperator, Filter, SearchParamPrefix, SearchParamType};
+use crate::search::executor::parse_has_param;
 
 pub fn param_to_filter<'r>(name: &str, mut value: &str, rd: &ResourceDef, sd: &SchemaDef) -> Result<Filter<'r>, EvalError> {
     debug!("creating a filter from the query parameter {} with value {}", name, value);
-    let at_name = name.split(":").next().unwrap();
+    // the type of a _has parameter is the type of the parameter at the end of its chain
+    let (at_name, rd) = if name.starts_with("_has:") {
+        let has = parse_has_param(name, sd)?;
+        (has.param, has.last_res_def())
+    }
+    else {
+        (name, rd)
+    };
+    let at_name = at_name.split(":").next().unwrap();
     let spd_and_expr = sd.get_search_param_expr_for_res(at_name, &rd.name);
     if let None = spd_and_expr {
         return Err(EvalError::new(format!("there is no search parameter defined with code {} on {}", at_name, rd.name)));
@@ -17,15 +26,16 @@ pub fn param_to_filter<'r>(name: &str, mut value: &str, rd: &ResourceDef, sd: &S
     let mut op = ComparisonOperator::EQ;
     match spd.param_type {
         SearchParamType::Number | SearchParamType::Date | SearchParamType::Quantity => {
-            if value.len() > 2 {
-                let (prefix_str, suffix) = value.split_at(2);
-                let mut prefix = SearchParamPrefix::from(prefix_str);
+            // the value is left untouched when it doesn't start with a prefix e.g 100 or 2021-01-01
+            if let (Some(prefix_str), Some(suffix)) = (value.get(..2), value.get(2..)) {
+                let prefix = SearchParamPrefix::from(prefix_str);
                 if prefix == SearchParamPrefix::Unknown {
-                    debug!("unknown prefix {}, defaulting to eq", prefix_str);
-                    prefix = SearchParamPrefix::Eq;
+                    debug!("no prefix in {}, defaulting to eq", value);
+                }
+                else {
+                    op = ComparisonOperator::from(prefix);
+                    value = suffix;
                 }
-                op = ComparisonOperator::from(prefix);
-                value = suffix;
             }
         },
         _ => {}
@@ -59,4 +69,38 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_has_param_to_filter() {
+        let tc = TestContainer::new();
+        let (db, sd) = tc.setup_db_with_example_patient().unwrap();
+        let rd = sd.resources.get("Patient").unwrap();
+        let filter = param_to_filter("_has:Observation:patient:code", "1234-5", rd, &sd).unwrap();
+        match &filter {
+            Filter::SimpleFilter {identifier, operator, value} => {
+                assert_eq!("_has:Observation:patient:code", identifier);
+                assert_eq!(&ComparisonOperator::EQ, operator);
+                assert_eq!("1234-5", value);
+            },
+            _ => {
+                assert!(false, "unexpected filter type");
+            }
+        }
+
+        // the prefix is determined by the type of the parameter at the end of the chain
+        let filter = param_to_filter("_has:Encounter:subject:_has:Observation:encounter:value-quantity", "gt5.4", rd, &sd).unwrap();
+        match &filter {
+            Filter::SimpleFilter {operator, value, ..} => {
+                assert_eq!(&ComparisonOperator::GT, operator);
+                assert_eq!("5.4", value);
+            },
+            _ => {
+                assert!(false, "unexpected filter type");
+            }
+        }
+
+        assert!(param_to_filter("_has:Observation:code:code", "1234-5", rd, &sd).is_err());
+        assert!(param_to_filter("_has:Observation:patient", "1234-5", rd, &sd).is_err());
+        assert!(param_to_filter("_has:Unknown:patient:code", "1234-5", rd, &sd).is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,260 @@
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::io::Cursor;
+use bson::Document;
+use crate::api::base::OperationOutcome;
+use crate::api::bundle::SearchSet;
+use crate::barn::{Barn, is_tombstone, to_ksuid};
+use crate::errors::{IssueType, RaError};
+use crate::res_schema::{SchemaDef, SearchParamDef, SearchParamExpr};
+use crate::ResourceDef;
+use crate::search::executor::find_search_param_expr;
+use crate::search::SearchParamType;
+
+/// a parsed value of _include or _revinclude e.g `Encounter:practitioner:Practitioner`
+#[derive(Debug, Eq, PartialEq)]
+pub struct IncludeParam<'a> {
+    /// the type of the resource holding the reference, `*` for any type
+    pub source: &'a str,
+    /// the code of the reference search parameter, `*` for all the reference parameters
+    pub code: &'a str,
+    pub target: Option<&'a str>,
+    pub iterate: bool,
+    pub rev: bool
+}
+
+/// parses the include parameter, the name may carry the iterate (or the older recurse) modifier
+pub fn parse_include<'a>(name: &str, value: &'a str) -> Result<IncludeParam<'a>, RaError> {
+    let mut parts = name.splitn(2, ':');
+    let rev = parts.next() == Some("_revinclude");
+    let iterate = match parts.next() {
+        None => false,
+        Some("iterate") | Some("recurse") => true,
+        Some(m) => {
+            return Err(RaError::bad_req(format!("unsupported modifier {} on {}", m, name)));
+        }
+    };
+
+    if value == "*" {
+        return Ok(IncludeParam{source: "*", code: "*", target: None, iterate, rev});
+    }
+
+    let mut parts = value.splitn(3, ':');
+    let source = parts.next().unwrap();
+    let code = parts.next();
+    if source.is_empty() || code.is_none() || code.unwrap().is_empty() {
+        return Err(RaError::bad_req(format!("invalid value {} of {}", value, name)));
+    }
+    let target = parts.next().filter(|t| !t.is_empty());
+
+    Ok(IncludeParam{source, code: code.unwrap(), target, iterate, rev})
+}
+
+/// fetches the resources referred by (_include) or referring to (_revinclude) the matches of the searchset.
+/// The includes marked with iterate are applied on the included resources as well until no new resources are found
+pub fn find_included(ss: &SearchSet, includes: &[IncludeParam], sd: &SchemaDef, db: &Barn) -> Result<Vec<Document>, RaError> {
+    for inc in includes {
+        validate(inc, sd)?;
+    }
+
+    let mut seen = HashSet::new();
+    let mut current = Vec::new();
+    for e in &ss.entries {
+        let rd = sd.get_res_def_by_name(e.resource.get_str("resourceType").unwrap_or_default())?;
+        let id = e.resource.get_str("id").ok().and_then(to_ksuid);
+        if let Some(id) = id {
+            let pk = rd.new_id(id.as_bytes());
+            if seen.insert(pk) {
+                current.push(pk);
+            }
+        }
+    }
+
+    let mut included = Vec::new();
+    let mut first = true;
+    while !current.is_empty() {
+        let mut found = Vec::new();
+        for inc in includes {
+            if !first && !inc.iterate {
+                continue;
+            }
+            for pk in &current {
+                if inc.rev {
+                    rev_include(inc, pk, sd, db, &mut found)?;
+                }
+                else {
+                    include(inc, pk, sd, db, &mut found)?;
+                }
+            }
+        }
+
+        found.retain(|pk| seen.insert(*pk));
+        for pk in &found {
+            let res = db.get_resource_by_pk(pk)?;
+            if let Some(res) = res {
+                let doc = Document::from_reader(&mut Cursor::new(res.as_ref()));
+                if let Err(e) = doc {
+                    let msg = format!("error while deserializing the document data fetched from database ({})", e.to_string());
+                    let oo = OperationOutcome::new_error(IssueType::Exception, msg);
+                    return Err(RaError::Custom{code: 500, outcome: oo});
+                }
+                let doc = doc.unwrap();
+                if !is_tombstone(&doc) {
+                    included.push(doc);
+                }
+            }
+        }
+
+        current = found;
+        first = false;
+    }
+
+    Ok(included)
+}
+
+fn validate(inc: &IncludeParam, sd: &SchemaDef) -> Result<(), RaError> {
+    if inc.source == "*" {
+        if inc.code != "*" {
+            return Err(RaError::bad_req(format!("the resource type must be specified for including by {}", inc.code)));
+        }
+    }
+    else {
+        let rd = sd.resources.get(inc.source);
+        if let None = rd {
+            return Err(RaError::bad_req(format!("unknown resource type {} in include parameter", inc.source)));
+        }
+        ref_params(inc.code, rd.unwrap(), sd)?;
+    }
+
+    if let Some(target) = inc.target {
+        if !sd.resources.contains_key(target) {
+            return Err(RaError::bad_req(format!("unknown target resource type {} in include parameter", target)));
+        }
+    }
+
+    Ok(())
+}
+
+/// returns the reference search parameters of the resource matching the given code
+fn ref_params<'s>(code: &str, rd: &ResourceDef, sd: &'s SchemaDef) -> Result<Vec<(&'s SearchParamDef, &'s SearchParamExpr)>, RaError> {
+    let mut params = Vec::new();
+    if code == "*" {
+        if let Some(search_params) = sd.get_search_params_of(&rd.name) {
+            for (_, param_id) in search_params {
+                let spd = sd.get_search_param(*param_id).unwrap();
+                if spd.param_type != SearchParamType::Reference {
+                    continue;
+                }
+                if let Some(Some(expr)) = spd.expressions.get(&rd.name) {
+                    params.push((spd, expr));
+                }
+            }
+        }
+        return Ok(params);
+    }
+
+    let (spd, expr) = sd.get_search_param_expr_for_res(code, &rd.name)
+        .and_then(|(spd, expr)| expr.map(|e| (spd, e)))
+        .ok_or_else(|| RaError::bad_req(format!("there is no search parameter defined with code {} on {}", code, rd.name)))?;
+    if spd.param_type != SearchParamType::Reference {
+        return Err(RaError::bad_req(format!("{} is not a reference search parameter of {}", code, rd.name)));
+    }
+    params.push((spd, expr));
+    Ok(params)
+}
+
+fn include(inc: &IncludeParam, pk: &[u8; 24], sd: &SchemaDef, db: &Barn, found: &mut Vec<[u8; 24]>) -> Result<(), RaError> {
+    let rd = sd.get_res_def_by_hash(&pk[..4])?;
+    if inc.source != "*" && inc.source != rd.name {
+        return Ok(());
+    }
+    let target = match inc.target {
+        Some(t) => Some(sd.get_res_def_by_name(t)?.hash),
+        None => None
+    };
+
+    let data = db.get_resource_by_pk(pk)?;
+    if let None = data {
+        return Ok(());
+    }
+    let data = data.unwrap();
+    for (spd, expr) in ref_params(inc.code, rd, sd)? {
+        let rows = db.gen_search_param_rows(pk, data.as_ref(), spd, expr, sd)?;
+        for (k, _) in rows {
+            // <search-param-hash><value-flag><target-type-hash><target-id><pk>
+            if k.len() != 53 || k[4] == 0 {
+                continue;
+            }
+            if let Some(target) = target {
+                if target != k[5..9] {
+                    continue;
+                }
+            }
+            found.push(k[5..29].try_into().unwrap());
+        }
+    }
+
+    Ok(())
+}
+
+fn rev_include(inc: &IncludeParam, pk: &[u8; 24], sd: &SchemaDef, db: &Barn, found: &mut Vec<[u8; 24]>) -> Result<(), RaError> {
+    let rd = sd.get_res_def_by_hash(&pk[..4])?;
+    if let Some(target) = inc.target {
+        if target != rd.name {
+            return Ok(());
+        }
+    }
+
+    if inc.code == "*" {
+        // all the referrals are found from the rows of the form <revinclude-hash><target-id><source-pk>
+        let source = match inc.source {
+            "*" => None,
+            s => Some(sd.get_res_def_by_name(s)?.hash)
+        };
+        let mut prefix = Vec::with_capacity(24);
+        prefix.extend_from_slice(&rd.revinclude_hash);
+        prefix.extend_from_slice(&pk[4..]);
+        for (k, _) in db.new_index_iter(&prefix) {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            if let Some(source) = source {
+                if source != k[24..28] {
+                    continue;
+                }
+            }
+            found.push(k[24..48].try_into().unwrap());
+        }
+        return Ok(());
+    }
+
+    let source = sd.get_res_def_by_name(inc.source)?;
+    let (_, expr) = find_search_param_expr(inc.code, source, sd)?;
+    let mut prefix = Vec::with_capacity(29);
+    prefix.extend_from_slice(&expr.hash);
+    prefix.push(1);
+    prefix.extend_from_slice(pk);
+    for (k, _) in db.new_index_iter(&prefix) {
+        if !k.starts_with(&prefix) {
+            break;
+        }
+        found.push(k[29..53].try_into().unwrap());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_include() {
+        assert_eq!(IncludeParam{source: "Encounter", code: "practitioner", target: None, iterate: false, rev: false}, parse_include("_include", "Encounter:practitioner").unwrap());
+        assert_eq!(IncludeParam{source: "Encounter", code: "subject", target: Some("Patient"), iterate: true, rev: false}, parse_include("_include:iterate", "Encounter:subject:Patient").unwrap());
+        assert_eq!(IncludeParam{source: "*", code: "*", target: None, iterate: false, rev: true}, parse_include("_revinclude", "*").unwrap());
+        assert_eq!(IncludeParam{source: "Encounter", code: "*", target: None, iterate: true, rev: true}, parse_include("_revinclude:recurse", "Encounter:*").unwrap());
+        assert!(parse_include("_include", "Encounter").is_err());
+        assert!(parse_include("_include:exact", "Encounter:subject").is_err());
+    }
+}
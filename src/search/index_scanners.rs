@@ -8,9 +8,13 @@ use crate::search::ComparisonOperator;
 
 pub mod string;
 pub mod and_or;
+pub mod date;
 pub mod not;
+pub mod number;
+pub mod quantity;
 pub mod reference;
 pub mod token;
+pub mod uri;
 
 pub type SelectedResourceKey = Result<Option<[u8; 24]>, EvalError>;
 
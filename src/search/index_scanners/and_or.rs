@@ -46,7 +46,9 @@ impl<'f> IndexScanner<'f> for AndOrIndexScanner<'f> {
                         break;
                     }
                 }
-                to_be_retained.insert(k, v);
+                if keep {
+                    to_be_retained.insert(k, v);
+                }
             }
             keys = to_be_retained;
         }
@@ -62,4 +64,30 @@ impl<'f> IndexScanner<'f> for AndOrIndexScanner<'f> {
 
         keys
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// returns the keys filled with the given bytes
+    struct FixedKeyScanner(Vec<u8>);
+
+    impl<'f> IndexScanner<'f> for FixedKeyScanner {
+        fn collect_all(&mut self) -> HashMap<[u8; 24], bool> {
+            self.0.iter().map(|b| ([*b; 24], true)).collect()
+        }
+    }
+
+    #[test]
+    fn test_and_or() {
+        let mut and = AndOrIndexScanner::new_and(vec![Box::new(FixedKeyScanner(vec![1, 2, 3])), Box::new(FixedKeyScanner(vec![2, 3, 4]))]);
+        let keys = and.collect_all();
+        // all the keys of the smallest operand were retained earlier
+        assert_eq!(2, keys.len());
+        assert!(keys.contains_key(&[2; 24]) && keys.contains_key(&[3; 24]));
+
+        let mut or = AndOrIndexScanner::new_or(vec![Box::new(FixedKeyScanner(vec![1, 2, 3])), Box::new(FixedKeyScanner(vec![2, 3, 4]))]);
+        assert_eq!(4, or.collect_all().len());
+    }
+}
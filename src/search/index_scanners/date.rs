@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use chrono::Utc;
+use rocksdb::DBIterator;
+use crate::errors::EvalError;
+use crate::search::ComparisonOperator;
+use crate::search::ComparisonOperator::*;
+use crate::search::index_scanners::IndexScanner;
+use crate::utils::date_utils::to_date_range;
+
+pub struct DateIndexScanner<'f, 'd: 'f> {
+    /// the range covered by the given value
+    low: i64,
+    high: i64,
+    itr: DBIterator<'d>,
+    index_prefix: &'f [u8],
+    op: &'f ComparisonOperator
+}
+
+impl<'f, 'd: 'f> DateIndexScanner<'f, 'd> {
+    pub fn new(input: &'f str, itr: DBIterator<'d>, op: &'f ComparisonOperator, index_prefix: &'f [u8]) -> Result<Self, EvalError> {
+        let (mut low, mut high) = to_date_range(input)?;
+        if *op == AP {
+            // approximately is 10% of the gap between now and the given value
+            let now = Utc::now().timestamp_millis();
+            let delta = (now - low).abs().max((now - high).abs()) / 10;
+            low = low.saturating_sub(delta);
+            high = high.saturating_add(delta);
+        }
+
+        Ok(DateIndexScanner{low, high, itr, index_prefix, op})
+    }
+
+    /// compares the range of the stored value with that of the given value
+    fn compare(&self, low: i64, high: i64) -> bool {
+        match self.op {
+            EQ => low >= self.low && high <= self.high,
+            NE => low < self.low || high > self.high,
+            GT => high > self.high,
+            LT => low < self.low,
+            GE => high > self.high || (low >= self.low && high <= self.high),
+            LE => low < self.low || (low >= self.low && high <= self.high),
+            SA => low > self.high,
+            EB => high < self.low,
+            AP => low <= self.high && high >= self.low,
+            _ => false
+        }
+    }
+}
+
+impl<'f, 'd: 'f> IndexScanner<'f> for DateIndexScanner<'f, 'd> {
+    fn collect_all(&mut self) -> HashMap<[u8; 24], bool> {
+        let mut res_keys = HashMap::new();
+        loop {
+            let row = self.itr.next();
+            if let None = row {
+                break;
+            }
+            let row = row.unwrap();
+            let row_prefix = &row.0[..4];
+            if row_prefix != self.index_prefix {
+                break;
+            }
+
+            let pos = row.0.len() - 24;
+            let has_val = row.0[4] == 1;
+            if !has_val || pos != 21 {
+                continue;
+            }
+
+            let low = i64::from_le_bytes(row.0[5..13].try_into().unwrap());
+            let high = i64::from_le_bytes(row.0[13..pos].try_into().unwrap());
+            if self.compare(low, high) {
+                let mut tmp: [u8; 24] = [0; 24];
+                tmp.copy_from_slice(&row.0[pos..]);
+                res_keys.insert(tmp, true);
+            }
+        }
+
+        res_keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use bson::doc;
+    use crate::utils::test_utils::{count_matches, TestContainer};
+    use super::*;
+
+    #[test]
+    fn test_date_search() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let (db, sd) = tc.setup_db_with_example_patient()?;
+        let rd = sd.resources.get("Encounter").unwrap();
+        // the times are around noon so that the dates given without a timezone match irrespective of the server's timezone
+        let periods = [("2021-01-10T12:00:00Z", "2021-01-10T13:00:00Z"), ("2021-01-20T12:00:00Z", "2021-02-05T12:00:00Z"), ("2021-03-01T12:00:00Z", "")];
+        for (start, end) in periods {
+            let mut period = doc! {"start": start};
+            if !end.is_empty() {
+                period.insert("end", end);
+            }
+            db.insert(rd, doc! {"resourceType": "Encounter", "status": "finished", "period": period}, &sd, false)?;
+        }
+
+        let mut candidates = vec![];
+        candidates.push(("2021-01-10", 1));
+        candidates.push(("eq2021-01", 1)); // the second encounter ends in February
+        candidates.push(("2021", 2)); // the third encounter is ongoing
+        candidates.push(("ne2021-01", 2));
+        candidates.push(("gt2021-01-31", 2));
+        candidates.push(("lt2021-01-20T12:00:00Z", 1));
+        candidates.push(("ge2021-02-05", 1));
+        candidates.push(("le2021-01-20", 1));
+        candidates.push(("sa2021-01-10T13:00:00Z", 2));
+        candidates.push(("eb2021-01-20T12:00:00Z", 1));
+        candidates.push(("sa2021-02-06", 1));
+        candidates.push(("ap2021-01-21", 3)); // widened by 10% of the years passed since then
+
+        for (input, expected) in candidates {
+            assert_eq!(expected, count_matches("date", input, rd, &sd, &db)?, "{}", input);
+        }
+
+        assert!(count_matches("date", "2021-13", rd, &sd, &db).is_err());
+        Ok(())
+    }
+}
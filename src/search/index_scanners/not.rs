@@ -16,7 +16,25 @@ impl<'f> NotIndexScanner<'f> {
 }
 
 impl<'f> IndexScanner<'f> for NotIndexScanner<'f> {
+    /// selects all the resources of the type except the ones selected by the child, this
+    /// includes the resources that do not have the element at all
     fn collect_all(&mut self) -> HashMap<[u8; 24], bool> {
-        todo!()
+        let excluded = self.child.collect_all();
+        let mut res_keys = HashMap::new();
+        for (k, _) in self.db.new_resource_key_iter(self.rd) {
+            if !k.starts_with(&self.rd.hash) {
+                break;
+            }
+            // only the primary keys of resources are 24 bytes long
+            if k.len() != 24 || excluded.contains_key(&k[..]) {
+                continue;
+            }
+
+            let mut tmp: [u8; 24] = [0; 24];
+            tmp.copy_from_slice(&k);
+            res_keys.insert(tmp, true);
+        }
+
+        res_keys
     }
 }
\ No newline at end of file
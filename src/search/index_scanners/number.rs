@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use rocksdb::DBIterator;
+use crate::errors::EvalError;
+use crate::search::ComparisonOperator;
+use crate::search::ComparisonOperator::*;
+use crate::search::index_scanners::IndexScanner;
+
+pub struct NumberIndexScanner<'f, 'd: 'f> {
+    range: NumberRange<'f>,
+    itr: DBIterator<'d>,
+    index_prefix: &'f [u8]
+}
+
+/// the given number along with the range of values considered equal to it
+#[derive(Debug)]
+pub struct NumberRange<'f> {
+    value: f64,
+    low: f64,
+    high: f64,
+    op: &'f ComparisonOperator
+}
+
+impl<'f, 'd: 'f> NumberIndexScanner<'f, 'd> {
+    pub fn new(input: &'f str, itr: DBIterator<'d>, op: &'f ComparisonOperator, index_prefix: &'f [u8]) -> Result<Self, EvalError> {
+        let range = NumberRange::new(input, op)?;
+        Ok(NumberIndexScanner{range, itr, index_prefix})
+    }
+}
+
+impl<'f> NumberRange<'f> {
+    pub fn new(input: &str, op: &'f ComparisonOperator) -> Result<Self, EvalError> {
+        let value = input.parse::<f64>();
+        if let Err(e) = value {
+            return Err(EvalError::new(format!("invalid number {} ({})", input, e.to_string())));
+        }
+        let value = value.unwrap();
+
+        let (mut low, mut high) = precision_range(input, value);
+        if *op == AP {
+            // approximately equal is within 10% of the value or the precision range whichever is wider
+            let delta = (value.abs() * 0.1).max(high - value);
+            low = value - delta;
+            high = value + delta;
+        }
+
+        Ok(NumberRange{value, low, high, op})
+    }
+
+    /// returns the range converted to another unit using the given (positive) factor
+    pub fn scale(&self, factor: f64) -> Self {
+        NumberRange{value: self.value * factor, low: self.low * factor, high: self.high * factor, op: self.op}
+    }
+
+    pub fn matches(&self, stored: f64) -> bool {
+        match self.op {
+            EQ => stored >= self.low && stored < self.high,
+            NE => stored < self.low || stored >= self.high,
+            GT | SA => stored > self.value,
+            LT | EB => stored < self.value,
+            GE => stored >= self.value,
+            LE => stored <= self.value,
+            AP => stored >= self.low && stored <= self.high,
+            _ => false
+        }
+    }
+}
+
+impl<'f, 'd: 'f> IndexScanner<'f> for NumberIndexScanner<'f, 'd> {
+    fn collect_all(&mut self) -> HashMap<[u8; 24], bool> {
+        let mut res_keys = HashMap::new();
+        loop {
+            let row = self.itr.next();
+            if let None = row {
+                break;
+            }
+            let row = row.unwrap();
+            let row_prefix = &row.0[..4];
+            if row_prefix != self.index_prefix {
+                break;
+            }
+
+            let pos = row.0.len() - 24;
+            let has_val = row.0[4] == 1;
+            if !has_val || pos != 13 {
+                continue;
+            }
+
+            let stored = f64::from_le_bytes(row.0[5..pos].try_into().unwrap());
+            if self.range.matches(stored) {
+                let mut tmp: [u8; 24] = [0; 24];
+                tmp.copy_from_slice(&row.0[pos..]);
+                res_keys.insert(tmp, true);
+            }
+        }
+
+        res_keys
+    }
+}
+
+/// returns the range implied by the significant digits of the given number,
+/// e.g 100 is [99.5, 100.5), 100.00 is [99.995, 100.005) and 1e2 is [95, 105)
+pub fn precision_range(input: &str, value: f64) -> (f64, f64) {
+    let input = input.to_ascii_lowercase();
+    // the precision of a number in exponent form is one digit finer than its mantissa
+    let (mantissa, exponent) = match input.split_once('e') {
+        Some((m, e)) => (m, e.parse::<i32>().unwrap_or(0) - 1),
+        None => (input.as_str(), 0)
+    };
+    let decimals = mantissa.split_once('.').map(|(_, d)| d.len() as i32).unwrap_or(0);
+    let half = 0.5 * 10f64.powi(exponent - decimals);
+
+    (value - half, value + half)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use bson::doc;
+    use crate::utils::test_utils::{count_matches, TestContainer};
+    use super::*;
+
+    #[test]
+    fn test_precision_range() {
+        assert_eq!((99.5, 100.5), precision_range("100", 100.0));
+        assert_eq!((95.0, 105.0), precision_range("1e2", 100.0));
+        let (low, high) = precision_range("100.00", 100.0);
+        assert!((low - 99.995).abs() < 1e-9 && (high - 100.005).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_number_search() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let (db, sd) = tc.setup_db_with_example_patient()?;
+        let rd = sd.resources.get("MolecularSequence").unwrap();
+        for start in [99, 100, 101, 110] {
+            db.insert(rd, doc! {"resourceType": "MolecularSequence", "coordinateSystem": 0, "variant": [{"start": start}]}, &sd, false)?;
+        }
+
+        let mut candidates = vec![];
+        candidates.push(("100", 1));
+        candidates.push(("eq100", 1));
+        candidates.push(("99.5", 0)); // the precision of the given value is 0.05
+        candidates.push(("1e2", 3));
+        candidates.push(("ne100", 3));
+        candidates.push(("gt100", 2));
+        candidates.push(("ge100", 3));
+        candidates.push(("lt100", 1));
+        candidates.push(("le100", 2));
+        candidates.push(("sa100", 2));
+        candidates.push(("eb100", 1));
+        candidates.push(("ap100", 4));
+        candidates.push(("ap99", 3));
+
+        for (input, expected) in candidates {
+            assert_eq!(expected, count_matches("variant-start", input, rd, &sd, &db)?, "{}", input);
+        }
+
+        assert!(count_matches("variant-start", "abc", rd, &sd, &db).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use rocksdb::DBIterator;
+use crate::errors::EvalError;
+use crate::search::ComparisonOperator;
+use crate::search::index_scanners::IndexScanner;
+use crate::search::index_scanners::number::NumberRange;
+use crate::utils::u32_from_le_bytes;
+use crate::utils::ucum;
+
+pub struct QuantityIndexScanner<'f, 'd: 'f> {
+    range: NumberRange<'f>,
+    system: Option<&'f str>,
+    code: Option<&'f str>,
+    /// the canonical UCUM unit of the given code and the range converted to it
+    canonical: Option<(&'static str, NumberRange<'f>)>,
+    itr: DBIterator<'d>,
+    index_prefix: &'f [u8]
+}
+
+impl<'f, 'd: 'f> QuantityIndexScanner<'f, 'd> {
+    /// the input is of the form number|system|code, the prefix must have been already removed
+    pub fn new(input: &'f str, itr: DBIterator<'d>, op: &'f ComparisonOperator, index_prefix: &'f [u8]) -> Result<Self, EvalError> {
+        let mut parts = input.splitn(3, '|');
+        let number = parts.next().unwrap();
+        let system = parts.next().filter(|s| !s.is_empty());
+        let code = parts.next().filter(|c| !c.is_empty());
+        if system.is_some() && code.is_none() {
+            return Err(EvalError::new(format!("missing code in the quantity {}", input)));
+        }
+
+        let range = NumberRange::new(number, op)?;
+        let mut canonical = None;
+        if let Some(c) = code {
+            if system.is_none() || system == Some(ucum::UCUM_SYSTEM) {
+                canonical = ucum::canonical_unit(c).map(|(unit, factor)| (unit, range.scale(factor)));
+            }
+        }
+
+        Ok(QuantityIndexScanner{range, system, code, canonical, itr, index_prefix})
+    }
+
+    /// the stored value is in the canonical unit if the stored code is a known UCUM code
+    fn compare(&self, stored: f64, stored_system: &[u8], stored_code: &[u8], original: f64) -> bool {
+        if let Some(system) = self.system {
+            if system.as_bytes() != stored_system {
+                return false;
+            }
+        }
+
+        match self.code {
+            None => self.range.matches(original),
+            Some(code) => {
+                if let Some((unit, canonical_range)) = &self.canonical {
+                    if unit.as_bytes() == stored_code {
+                        return canonical_range.matches(stored);
+                    }
+                }
+                code.as_bytes() == stored_code && self.range.matches(stored)
+            }
+        }
+    }
+}
+
+impl<'f, 'd: 'f> IndexScanner<'f> for QuantityIndexScanner<'f, 'd> {
+    fn collect_all(&mut self) -> HashMap<[u8; 24], bool> {
+        let mut res_keys = HashMap::new();
+        loop {
+            let row = self.itr.next();
+            if let None = row {
+                break;
+            }
+            let row = row.unwrap();
+            let row_prefix = &row.0[..4];
+            if row_prefix != self.index_prefix {
+                break;
+            }
+
+            let pos = row.0.len() - 24;
+            let has_val = row.0[4] == 1;
+            if !has_val || row.1.len() != 8 {
+                continue;
+            }
+
+            // <value><system-length><system><code-length><code>
+            let stored = f64::from_le_bytes(row.0[5..13].try_into().unwrap());
+            let sys_len = u32_from_le_bytes(&row.0[13..17]) as usize;
+            let stored_system = &row.0[17..17 + sys_len];
+            let code_start = 21 + sys_len;
+            let stored_code = &row.0[code_start..pos];
+            let original = f64::from_le_bytes(row.1[..8].try_into().unwrap());
+            if self.compare(stored, stored_system, stored_code, original) {
+                let mut tmp: [u8; 24] = [0; 24];
+                tmp.copy_from_slice(&row.0[pos..]);
+                res_keys.insert(tmp, true);
+            }
+        }
+
+        res_keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use bson::doc;
+    use crate::utils::test_utils::{count_matches, TestContainer};
+    use super::*;
+
+    #[test]
+    fn test_quantity_search() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let (db, sd) = tc.setup_db_with_example_patient()?;
+        let rd = sd.resources.get("Observation").unwrap();
+        let quantities = [(5400.0, Some(ucum::UCUM_SYSTEM), "ug"), (5.4, Some(ucum::UCUM_SYSTEM), "mg"), (5.4, Some("http://example.com/units"), "mg"), (120.0, None, "mm[Hg]")];
+        for (value, system, code) in quantities {
+            let mut quantity = doc! {"value": value, "unit": code, "code": code};
+            if let Some(system) = system {
+                quantity.insert("system", system);
+            }
+            db.insert(rd, doc! {"resourceType": "Observation", "status": "final", "code": {"text": "test"}, "valueQuantity": quantity}, &sd, false)?;
+        }
+
+        let mut candidates = vec![];
+        candidates.push(("5.4|http://unitsofmeasure.org|mg", 2));
+        candidates.push(("5400|http://unitsofmeasure.org|ug", 2));
+        candidates.push(("0.0054|http://unitsofmeasure.org|g", 2));
+        candidates.push(("5.4||mg", 3)); // the code alone matches irrespective of the system
+        candidates.push(("5.4|http://example.com/units|mg", 1));
+        candidates.push(("5.4", 2)); // compared with the values in the original units
+        candidates.push(("gt5|http://unitsofmeasure.org|mg", 2));
+        candidates.push(("lt5|http://unitsofmeasure.org|g", 2));
+        candidates.push(("ge5.5|http://unitsofmeasure.org|mg", 0));
+        candidates.push(("ap5|http://unitsofmeasure.org|mg", 2));
+        candidates.push(("16||kPa", 1)); // 120 mm[Hg] is 15.99864 kPa
+        candidates.push(("16|http://unitsofmeasure.org|kPa", 0)); // the system of mm[Hg] was not given
+        candidates.push(("ne1|http://unitsofmeasure.org|g", 2));
+
+        for (input, expected) in candidates {
+            assert_eq!(expected, count_matches("value-quantity", input, rd, &sd, &db)?, "{}", input);
+        }
+
+        assert!(count_matches("value-quantity", "5.4|http://unitsofmeasure.org|", rd, &sd, &db).is_err());
+        Ok(())
+    }
+}
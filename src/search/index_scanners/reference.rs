@@ -46,6 +46,15 @@ pub struct ReferenceChainIndexScanner<'f> {
     chain: Rc<ChainedParam<'f>>
 }
 
+/// finds the resources referred by the resources selected by the inner scanner, used for
+/// evaluating the _has parameter
+pub struct ReverseChainIndexScanner<'f> {
+    inner: Box<dyn IndexScanner<'f> + 'f>,
+    itr: DBIterator<'f>,
+    index_prefix: &'f [u8],
+    target_type: [u8; 4]
+}
+
 pub struct ChainedParam<'f> {
     name: &'f str,
     modifier: Modifier<'f>,
@@ -68,6 +77,11 @@ pub fn new_reference_chain_scanner<'f>(chain: Rc<ChainedParam<'f>>, ref_type: Op
     ReferenceChainIndexScanner{itr, db, sd, index_prefix, chain, ref_type}
 }
 
+pub fn new_reverse_chain_scanner<'f>(inner: Box<dyn IndexScanner<'f> + 'f>, db: &'f Barn, index_prefix: &'f [u8], target_type: [u8; 4]) -> ReverseChainIndexScanner<'f> {
+    let itr = db.new_index_iter(index_prefix);
+    ReverseChainIndexScanner{inner, itr, index_prefix, target_type}
+}
+
 impl<'f, 'd: 'f> IndexScanner<'f> for ReferenceIndexScanner<'f, 'd> {
     fn collect_all(&mut self) -> HashMap<[u8; 24], bool> {
         let mut res_keys = HashMap::new();
@@ -213,6 +227,42 @@ impl<'f> IndexScanner<'f> for ReferenceIdIndexScanner<'f> {
     }
 }
 
+impl<'f> IndexScanner<'f> for ReverseChainIndexScanner<'f> {
+    fn collect_all(&mut self) -> HashMap<[u8; 24], bool> {
+        let mut res_keys = HashMap::new();
+        let referring_keys = self.inner.collect_all();
+        if referring_keys.is_empty() {
+            return res_keys;
+        }
+
+        loop {
+            let row = self.itr.next();
+            if let None = row {
+                break;
+            }
+            let row = row.unwrap();
+            let row_prefix = &row.0[..4];
+            if row_prefix != self.index_prefix {
+                break;
+            }
+
+            let pos = row.0.len() - 24;
+            let hasVal = row.0[4] == 1;
+            if !hasVal {
+                continue;
+            }
+
+            if row.0[5..9] == self.target_type && referring_keys.contains_key(&row.0[pos..]) {
+                let mut tmp: [u8; 24] = [0; 24];
+                tmp.copy_from_slice(&row.0[5..pos]);
+                res_keys.insert(tmp, true);
+            }
+        }
+
+        res_keys
+    }
+}
+
 impl<'f> ChainedParam<'f> {
     pub fn new(name: &'f str, modifier: Modifier<'f>, value: Option<&'f str>, operator: &'f ComparisonOperator) -> ChainedParam<'f> {
         ChainedParam{name, modifier, value, child: None, operator}
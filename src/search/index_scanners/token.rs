@@ -61,6 +61,7 @@ impl<'f, 'd: 'f> TokenIndexScanner<'f, 'd> {
 
                 sys_match && code_match
             }
+            // Modifier::Not is evaluated as the complement of Modifier::None, see NotIndexScanner
             _ => {
                 false
             }
@@ -113,8 +114,10 @@ impl<'f, 'd: 'f> IndexScanner<'f> for TokenIndexScanner<'f, 'd> {
 #[cfg(test)]
 mod tests {
     use anyhow::Error;
+    use bson::doc;
     use crate::search;
     use crate::search::executor::to_index_scanner;
+    use crate::search::filter_converter::param_to_filter;
     use crate::utils::test_utils::TestContainer;
     use super::*;
 
@@ -140,4 +143,26 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_token_not_modifier() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let (db, sd) = tc.setup_db_with_example_patient()?;
+        let rd = sd.resources.get("Patient").unwrap();
+        db.insert(rd, doc! {"resourceType": "Patient", "gender": "female"}, &sd, false)?;
+        db.insert(rd, doc! {"resourceType": "Patient"}, &sd, false)?;
+
+        // the patient without gender matches both the :not searches
+        let candidates = [("gender:not", "male", 2), ("gender:not", "female", 2), ("gender:not", "other", 3), ("gender", "male", 1)];
+        for (name, value, expected) in candidates {
+            let filter = param_to_filter(name, value, rd, &sd)?;
+            let mut idx_scanner = to_index_scanner(&filter, &rd, &sd, &db)?;
+            assert_eq!(expected, idx_scanner.collect_all().len());
+        }
+
+        let filter = search::parse_filter("not(gender eq \"male\")")?;
+        let mut idx_scanner = to_index_scanner(&filter, &rd, &sd, &db)?;
+        assert_eq!(2, idx_scanner.collect_all().len());
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use rocksdb::DBIterator;
+use crate::errors::EvalError;
+use crate::search::index_scanners::IndexScanner;
+use crate::search::Modifier;
+
+pub struct UriIndexScanner<'f, 'd: 'f> {
+    value: &'f [u8],
+    itr: DBIterator<'d>,
+    index_prefix: &'f [u8],
+    modifier: Modifier<'f>
+}
+
+impl<'f, 'd: 'f> UriIndexScanner<'f, 'd> {
+    pub fn new(input: &'f str, itr: DBIterator<'d>, index_prefix: &'f [u8], modifier: Modifier<'f>) -> Result<Self, EvalError> {
+        match modifier {
+            Modifier::None => {},
+            Modifier::Above | Modifier::Below => {
+                // URNs are not hierarchical
+                if input.starts_with("urn:") {
+                    return Err(EvalError::new(format!("{:?} modifier cannot be used with the URN {}", modifier, input)));
+                }
+            },
+            _ => {
+                return Err(EvalError::new(format!("unsupported modifier {:?} on URI search parameter", modifier)));
+            }
+        }
+
+        Ok(UriIndexScanner{value: input.as_bytes(), itr, index_prefix, modifier})
+    }
+
+    fn compare(&self, stored: &[u8]) -> bool {
+        match self.modifier {
+            // the stored URI is a sub path of the given value e.g http://acme.org/fhir/ValueSet/123 is below http://acme.org/fhir/
+            Modifier::Below => is_sub_path(stored, self.value),
+            // the given value is a sub path of the stored URI
+            Modifier::Above => is_sub_path(self.value, stored),
+            _ => stored == self.value
+        }
+    }
+}
+
+/// checks if the path is same as the parent or is present under one of its segments
+fn is_sub_path(path: &[u8], parent: &[u8]) -> bool {
+    if !path.starts_with(parent) {
+        return false;
+    }
+
+    path.len() == parent.len() || parent.ends_with(b"/") || path[parent.len()] == b'/'
+}
+
+impl<'f, 'd: 'f> IndexScanner<'f> for UriIndexScanner<'f, 'd> {
+    fn collect_all(&mut self) -> HashMap<[u8; 24], bool> {
+        let mut res_keys = HashMap::new();
+        loop {
+            let row = self.itr.next();
+            if let None = row {
+                break;
+            }
+            let row = row.unwrap();
+            let row_prefix = &row.0[..4];
+            if row_prefix != self.index_prefix {
+                break;
+            }
+
+            let pos = row.0.len() - 24;
+            let has_val = row.0[4] == 1;
+            if !has_val {
+                continue;
+            }
+
+            if self.compare(&row.0[5..pos]) {
+                let mut tmp: [u8; 24] = [0; 24];
+                tmp.copy_from_slice(&row.0[pos..]);
+                res_keys.insert(tmp, true);
+            }
+        }
+
+        res_keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use bson::doc;
+    use crate::utils::test_utils::{count_matches, TestContainer};
+    use super::*;
+
+    #[test]
+    fn test_is_sub_path() {
+        assert!(is_sub_path(b"http://acme.org/fhir/ValueSet/123", b"http://acme.org/fhir"));
+        assert!(is_sub_path(b"http://acme.org/fhir/ValueSet/123", b"http://acme.org/fhir/"));
+        assert!(is_sub_path(b"http://acme.org/fhir", b"http://acme.org/fhir"));
+        assert!(!is_sub_path(b"http://acme.org/fhir2/ValueSet", b"http://acme.org/fhir"));
+        assert!(!is_sub_path(b"http://acme.org", b"http://acme.org/fhir"));
+    }
+
+    #[test]
+    fn test_uri_search() -> Result<(), Error> {
+        let tc = TestContainer::new();
+        let (db, sd) = tc.setup_db_with_example_patient()?;
+        let rd = sd.resources.get("ValueSet").unwrap();
+        for url in ["http://acme.org/fhir/ValueSet/123", "http://acme.org/fhir/ValueSet/123/_history/2", "http://acme.org/fhir2/ValueSet/123", "urn:oid:1.2.3"] {
+            db.insert(rd, doc! {"resourceType": "ValueSet", "status": "active", "url": url}, &sd, false)?;
+        }
+
+        let mut candidates = vec![];
+        candidates.push(("url", "http://acme.org/fhir/ValueSet/123", 1));
+        candidates.push(("url", "http://acme.org/fhir/ValueSet", 0));
+        candidates.push(("url", "urn:oid:1.2.3", 1));
+        candidates.push(("url:below", "http://acme.org/fhir", 2));
+        candidates.push(("url:below", "http://acme.org/", 3));
+        candidates.push(("url:above", "http://acme.org/fhir/ValueSet/123/_history/5", 1));
+        candidates.push(("url:above", "http://acme.org/fhir/ValueSet/123/_history/2", 2));
+        candidates.push(("url:above", "http://acme.org/fhir/ValueSet", 0));
+
+        for (name, input, expected) in candidates {
+            assert_eq!(expected, count_matches(name, input, rd, &sd, &db)?, "{}={}", name, input);
+        }
+
+        assert!(count_matches("url:below", "urn:oid:1.2", rd, &sd, &db).is_err());
+        assert!(count_matches("url:exact", "urn:oid:1.2.3", rd, &sd, &db).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use rawbson::Doc;
+use crate::barn::{Barn, to_document};
+use crate::errors::{EvalError, RaError};
+use crate::res_schema::SchemaDef;
+use crate::ResourceDef;
+use crate::search::executor::find_search_param_expr;
+use crate::search::SearchParamType;
+use crate::utils::bson_utils;
+
+/// the parameter used when no sort order is given, keeps the order of results stable across pages
+const DEFAULT_SORT: &str = "_lastUpdated";
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct SortParam<'a> {
+    pub name: &'a str,
+    pub desc: bool
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SortValue {
+    Number(f64),
+    Date(i64),
+    Text(Vec<u8>)
+}
+
+/// the key of a matched resource along with its values of the sort parameters
+#[derive(Clone, Debug, PartialEq)]
+pub struct SortKey {
+    pub values: Vec<Option<SortValue>>,
+    pub pk: [u8; 24]
+}
+
+/// parses the value of _sort (e.g `status,-date`)
+pub fn parse_sort(sort: Option<&str>) -> Vec<SortParam> {
+    let sort = sort.unwrap_or(DEFAULT_SORT);
+    sort.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| {
+        match s.strip_prefix('-') {
+            Some(name) => SortParam{name, desc: true},
+            None => SortParam{name: s, desc: false}
+        }
+    }).collect()
+}
+
+/// sorts the keys of the matched resources in memory. Values of the sort parameters are gathered
+/// from the matched resources alone, _lastUpdated is read from its dedicated key and the rest are
+/// evaluated on the resource. Keys with equal values are ordered by their primary keys
+pub fn sort_keys(keys: HashMap<[u8; 24], bool>, params: &[SortParam], rd: &ResourceDef, sd: &SchemaDef, db: &Barn) -> Result<Vec<SortKey>, RaError> {
+    let mut exprs = Vec::with_capacity(params.len());
+    for p in params {
+        match p.name {
+            "_id" | "_lastUpdated" => exprs.push(None),
+            name => {
+                let (spd, expr) = find_search_param_expr(name, rd, sd)?;
+                match spd.param_type {
+                    SearchParamType::String | SearchParamType::Number | SearchParamType::Date | SearchParamType::Token => {},
+                    _ => {
+                        return Err(RaError::bad_req(format!("sorting on the {:?} search parameter {} is not supported", spd.param_type, name)));
+                    }
+                }
+                exprs.push(Some((spd, expr)));
+            }
+        }
+    }
+
+    let needs_resource = params.iter().any(|p| p.name != "_lastUpdated");
+    let mut sort_keys = Vec::with_capacity(keys.len());
+    for (pk, _) in keys {
+        let mut res = if needs_resource { db.get_resource_by_pk(&pk)?.map(|r| r.to_vec()) } else { None };
+
+        let mut values = Vec::with_capacity(params.len());
+        for (p, expr) in params.iter().zip(&exprs) {
+            let val = match (p.name, expr) {
+                ("_lastUpdated", _) => {
+                    match db.get_last_updated(&pk)? {
+                        Some(millis) => Some(SortValue::Date(millis)),
+                        None => {
+                            // resources written before the lastUpdated keys were introduced
+                            if let None = res {
+                                res = db.get_resource_by_pk(&pk)?.map(|r| r.to_vec());
+                            }
+                            match &res {
+                                Some(res) => {
+                                    let doc = to_document(res)?;
+                                    bson_utils::get_time(&doc, "meta.lastUpdated").map(|t| SortValue::Date(t.timestamp_millis()))
+                                },
+                                None => None
+                            }
+                        }
+                    }
+                },
+                (_, Some((spd, expr))) => {
+                    match &res {
+                        Some(res) => {
+                            // the lowest value of a repeating element is used for ascending order and the highest for descending
+                            let mut val: Option<SortValue> = None;
+                            for (k, _) in db.gen_search_param_rows(&pk, res, spd, expr, sd)? {
+                                let v = parse_index_value(&spd.param_type, &k[4..k.len() - 24], p.desc);
+                                if let Some(v) = v {
+                                    let replace = match &val {
+                                        None => true,
+                                        Some(current) => {
+                                            let ord = cmp_values(&v, current);
+                                            (p.desc && ord == Ordering::Greater) || (!p.desc && ord == Ordering::Less)
+                                        }
+                                    };
+                                    if replace {
+                                        val = Some(v);
+                                    }
+                                }
+                            }
+                            val
+                        },
+                        None => None
+                    }
+                },
+                _ => {
+                    match &res {
+                        Some(res) => {
+                            let doc = Doc::new(res.as_slice()).map_err(EvalError::from)?;
+                            doc.get_str("id").map_err(EvalError::from)?.map(|id| SortValue::Text(id.as_bytes().to_vec()))
+                        },
+                        None => None
+                    }
+                }
+            };
+            values.push(val);
+        }
+        sort_keys.push(SortKey{values, pk});
+    }
+
+    sort_keys.sort_unstable_by(|a, b| compare(a, b, params));
+    Ok(sort_keys)
+}
+
+/// compares the sort keys as per the direction of each parameter, resources without
+/// a value are placed at the end irrespective of the direction
+pub fn compare(a: &SortKey, b: &SortKey, params: &[SortParam]) -> Ordering {
+    for (i, p) in params.iter().enumerate() {
+        let ord = match (&a.values[i], &b.values[i]) {
+            (Some(x), Some(y)) => {
+                let ord = cmp_values(x, y);
+                if p.desc { ord.reverse() } else { ord }
+            },
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+
+    a.pk.cmp(&b.pk)
+}
+
+fn cmp_values(a: &SortValue, b: &SortValue) -> Ordering {
+    match (a, b) {
+        (SortValue::Number(x), SortValue::Number(y)) => x.partial_cmp(y).unwrap_or(Ordering::Equal),
+        (SortValue::Date(x), SortValue::Date(y)) => x.cmp(y),
+        (SortValue::Text(x), SortValue::Text(y)) => x.cmp(y),
+        _ => Ordering::Equal
+    }
+}
+
+/// extracts the sortable value from the key of an index row, the given data excludes
+/// the search param's hash and the resource's primary key
+fn parse_index_value(param_type: &SearchParamType, data: &[u8], desc: bool) -> Option<SortValue> {
+    // the first byte is the NULL value flag
+    if data.is_empty() || data[0] == 0 {
+        return None;
+    }
+
+    let data = &data[1..];
+    let val = match param_type {
+        SearchParamType::String => SortValue::Text(data.to_vec()),
+        SearchParamType::Number => SortValue::Number(f64::from_le_bytes(data[..8].try_into().unwrap())),
+        SearchParamType::Date => {
+            // dates are stored as the range they cover, the start of the range is used for ascending order
+            // and the end for descending, an open end of a Period is substituted with the other end
+            let low = i64::from_le_bytes(data[..8].try_into().unwrap());
+            let high = i64::from_le_bytes(data[8..16].try_into().unwrap());
+            let (first, second) = if desc { (high, low) } else { (low, high) };
+            let val = if first != i64::MIN && first != i64::MAX { first } else { second };
+            if val == i64::MIN || val == i64::MAX {
+                return None;
+            }
+            SortValue::Date(val)
+        },
+        SearchParamType::Token => {
+            // tokens are sorted by code and then by system
+            let (system, rest) = read_len_prefixed(data);
+            let (code, _) = read_len_prefixed(rest);
+            let mut val = code.to_vec();
+            val.push(0);
+            val.extend_from_slice(system);
+            SortValue::Text(val)
+        },
+        _ => {
+            return None;
+        }
+    };
+
+    Some(val)
+}
+
+fn read_len_prefixed(data: &[u8]) -> (&[u8], &[u8]) {
+    let len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+    (&data[4..4 + len], &data[4 + len..])
+}
+
+/// serializes the sort key for including in a continuation token
+pub fn encode_sort_key(sk: &SortKey) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&sk.pk);
+    for v in &sk.values {
+        match v {
+            None => data.push(0),
+            Some(SortValue::Number(n)) => {
+                data.push(1);
+                data.extend_from_slice(&n.to_le_bytes());
+            },
+            Some(SortValue::Date(d)) => {
+                data.push(2);
+                data.extend_from_slice(&d.to_le_bytes());
+            },
+            Some(SortValue::Text(t)) => {
+                data.push(3);
+                data.extend_from_slice(&(t.len() as u32).to_le_bytes());
+                data.extend_from_slice(t);
+            }
+        }
+    }
+
+    data
+}
+
+pub fn decode_sort_key(data: &[u8]) -> Option<SortKey> {
+    if data.len() < 24 {
+        return None;
+    }
+    let pk: [u8; 24] = data[..24].try_into().unwrap();
+    let mut values = Vec::new();
+    let mut data = &data[24..];
+    while !data.is_empty() {
+        let tag = data[0];
+        data = &data[1..];
+        let v = match tag {
+            0 => None,
+            1 | 2 => {
+                let bytes: [u8; 8] = data.get(..8)?.try_into().unwrap();
+                data = &data[8..];
+                if tag == 1 { Some(SortValue::Number(f64::from_le_bytes(bytes))) } else { Some(SortValue::Date(i64::from_le_bytes(bytes))) }
+            },
+            3 => {
+                let len = u32::from_le_bytes(data.get(..4)?.try_into().unwrap()) as usize;
+                let t = data.get(4..4 + len)?.to_vec();
+                data = &data[4 + len..];
+                Some(SortValue::Text(t))
+            },
+            _ => return None
+        };
+        values.push(v);
+    }
+
+    Some(SortKey{values, pk})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sort() {
+        assert_eq!(vec![SortParam{name: "_lastUpdated", desc: false}], parse_sort(None));
+        assert_eq!(vec![SortParam{name: "status", desc: false}, SortParam{name: "date", desc: true}], parse_sort(Some("status,-date")));
+    }
+
+    #[test]
+    fn test_sort_key_encoding() {
+        let sk = SortKey{values: vec![None, Some(SortValue::Number(1.5)), Some(SortValue::Date(-10)), Some(SortValue::Text(b"abc".to_vec()))], pk: [7; 24]};
+        let data = encode_sort_key(&sk);
+        assert_eq!(Some(sk), decode_sort_key(&data));
+        assert_eq!(None, decode_sort_key(&data[..30]));
+    }
+
+    #[test]
+    fn test_parse_date_index_value() {
+        let row = |low: i64, high: i64| {
+            let mut data = vec![1];
+            data.extend_from_slice(&low.to_le_bytes());
+            data.extend_from_slice(&high.to_le_bytes());
+            data
+        };
+        assert_eq!(Some(SortValue::Date(10)), parse_index_value(&SearchParamType::Date, &row(10, 20), false));
+        assert_eq!(Some(SortValue::Date(20)), parse_index_value(&SearchParamType::Date, &row(10, 20), true));
+        // a Period without a start or an end
+        assert_eq!(Some(SortValue::Date(20)), parse_index_value(&SearchParamType::Date, &row(i64::MIN, 20), false));
+        assert_eq!(Some(SortValue::Date(10)), parse_index_value(&SearchParamType::Date, &row(10, i64::MAX), true));
+        assert_eq!(None, parse_index_value(&SearchParamType::Date, &row(i64::MIN, i64::MAX), false));
+        assert_eq!(None, parse_index_value(&SearchParamType::Date, &[0], false));
+    }
+
+    #[test]
+    fn test_compare() {
+        let params = parse_sort(Some("-date,name"));
+        let a = SortKey{values: vec![Some(SortValue::Date(2)), Some(SortValue::Text(b"b".to_vec()))], pk: [1; 24]};
+        let b = SortKey{values: vec![Some(SortValue::Date(1)), Some(SortValue::Text(b"a".to_vec()))], pk: [2; 24]};
+        let c = SortKey{values: vec![None, Some(SortValue::Text(b"a".to_vec()))], pk: [0; 24]};
+        let d = SortKey{values: vec![Some(SortValue::Date(2)), Some(SortValue::Text(b"a".to_vec()))], pk: [3; 24]};
+        let mut keys = vec![c.clone(), b.clone(), a.clone(), d.clone()];
+        keys.sort_unstable_by(|x, y| compare(x, y, &params));
+        assert_eq!(vec![d, a, b, c], keys);
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, NaiveDate, Utc};
 use crc32fast::Hasher;
 
 pub mod test_utils;
@@ -6,6 +7,8 @@ pub mod bson_utils;
 pub mod resources;
 pub mod validator;
 pub mod norm_utils;
+pub mod date_utils;
+pub mod ucum;
 
 pub fn u32_from_le_bytes(b: &[u8]) -> u32 {
     let mut d : u32 = 0;
@@ -31,9 +34,25 @@ pub fn prefix_id(prefix: &[u8], ksid: &[u8]) -> [u8; 24]{
     tmp
 }
 
+/// parses the given value as an instant (e.g 2022-02-06T11:45:00Z) or as a date (e.g 2022-02-06)
+/// the time defaults to the start of the day if only the date is present
+pub fn parse_instant(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t.with_timezone(&Utc));
+    }
+
+    if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Some(DateTime::from_utc(d.and_hms(0, 0, 0), Utc));
+    }
+
+    None
+}
+
 #[cfg(test)]
 mod tests {
+    use chrono::{TimeZone, Utc};
     use ksuid::Ksuid;
+    use crate::utils::parse_instant;
 
     // a test function to generate the Ksuid
     #[test]
@@ -41,4 +60,13 @@ mod tests {
         let id = Ksuid::generate();
         println!("{}", id.to_base62());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_instant() {
+        let expected = Utc.ymd(2022, 2, 6).and_hms(11, 45, 0);
+        assert_eq!(Some(expected), parse_instant("2022-02-06T11:45:00Z"));
+        assert_eq!(Some(expected), parse_instant("2022-02-06T17:15:00+05:30"));
+        assert_eq!(Some(Utc.ymd(2022, 2, 6).and_hms(0, 0, 0)), parse_instant("2022-02-06"));
+        assert_eq!(None, parse_instant("06-02-2022"));
+    }
+}
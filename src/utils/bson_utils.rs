@@ -43,6 +43,14 @@ pub fn get_int(doc: &Document, path: &str) -> i64 {
             if let Some(val) = val {
                 return val as i64;
             }
+
+            // integers stored as strings, e.g versionId
+            let val = o.as_str();
+            if let Some(val) = val {
+                if let Ok(val) = val.parse::<i64>() {
+                    return val;
+                }
+            }
         }
     }
 
@@ -90,4 +98,12 @@ mod tests {
         let last_modified = last_modified.format("%a, %d %m %Y %H:%M:%S GMT").to_string();
         assert_eq!("Sun, 06 02 2022 11:45:00 GMT", last_modified);
     }
+
+    #[test]
+    fn test_get_int_from_string() {
+        let doc = bson!({"id": "abcd", "meta": { "versionId": "2"}});
+        let doc = doc.as_document().unwrap();
+        assert_eq!(2, get_int(doc, "meta.versionId"));
+        assert_eq!(-1, get_int(doc, "id"));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,215 @@
+use chrono::{Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone};
+use lazy_static::lazy_static;
+use rawbson::Doc;
+use rawbson::elem::{Element, ElementType};
+use regex::Regex;
+use crate::errors::EvalError;
+
+lazy_static! {
+    static ref DATE_RE: Regex = Regex::new(r"^(\d{4})(-(\d{2})(-(\d{2})(T(\d{2}):(\d{2})(:(\d{2})(\.(\d+))?)?(Z|[+-]\d{2}:\d{2})?)?)?)?$").unwrap();
+}
+
+/// returns the range of milliseconds, both ends inclusive, covered by the precision of a date, dateTime or instant
+/// e.g 1974 covers the whole year 1974. Values without a timezone are considered to be in the server's timezone
+pub fn to_date_range(val: &str) -> Result<(i64, i64), EvalError> {
+    let caps = DATE_RE.captures(val);
+    if let None = caps {
+        return Err(EvalError::new(format!("invalid date {}", val)));
+    }
+    let caps = caps.unwrap();
+    let num = |i: usize| caps.get(i).map(|m| m.as_str().parse::<u32>().unwrap());
+    let year = num(1).unwrap() as i32;
+    let month = num(3);
+    let day = num(5);
+    let hour = num(7);
+    let minute = num(8);
+    let second = num(10);
+    let fraction = caps.get(12).map(|m| m.as_str());
+
+    let mut millis = 0;
+    let mut fraction_unit = 1000;
+    if let Some(f) = fraction {
+        // anything beyond milliseconds is ignored
+        let f = &f[..f.len().min(3)];
+        fraction_unit = 10_i64.pow(3 - f.len() as u32);
+        millis = f.parse::<u32>().unwrap() * fraction_unit as u32;
+    }
+
+    let start = NaiveDate::from_ymd_opt(year, month.unwrap_or(1), day.unwrap_or(1))
+        .and_then(|d| d.and_hms_milli_opt(hour.unwrap_or(0), minute.unwrap_or(0), second.unwrap_or(0), millis));
+    if let None = start {
+        return Err(EvalError::new(format!("invalid date {}", val)));
+    }
+    let start = start.unwrap();
+
+    let end = if second.is_some() {
+        start + Duration::milliseconds(fraction_unit)
+    }
+    else if minute.is_some() {
+        start + Duration::minutes(1)
+    }
+    else if day.is_some() {
+        start + Duration::days(1)
+    }
+    else if let Some(month) = month {
+        let (y, m) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+        NaiveDate::from_ymd(y, m, 1).and_hms(0, 0, 0)
+    }
+    else {
+        NaiveDate::from_ymd(year + 1, 1, 1).and_hms(0, 0, 0)
+    };
+
+    let offset = match caps.get(13).map(|m| m.as_str()) {
+        Some("Z") => Some(FixedOffset::east(0)),
+        Some(tz) => {
+            let hours = tz[1..3].parse::<i32>().unwrap();
+            let minutes = tz[4..6].parse::<i32>().unwrap();
+            let secs = hours * 3600 + minutes * 60;
+            let offset = if tz.starts_with('-') { FixedOffset::west_opt(secs) } else { FixedOffset::east_opt(secs) };
+            if let None = offset {
+                return Err(EvalError::new(format!("invalid timezone offset in date {}", val)));
+            }
+            offset
+        },
+        None => None
+    };
+
+    Ok((to_millis(&start, offset), to_millis(&end, offset) - 1))
+}
+
+/// returns the range covered by a date, dateTime, instant, Period or Timing element. The missing
+/// ends of a Period are treated as unbounded
+pub fn element_to_date_range(el: &Element) -> Result<Option<(i64, i64)>, EvalError> {
+    match el.element_type() {
+        ElementType::String => {
+            let range = to_date_range(el.as_str()?)?;
+            Ok(Some(range))
+        },
+        ElementType::EmbeddedDocument => {
+            let doc = el.as_document()?;
+            let range = period_range(doc)?;
+            if range.is_some() {
+                return Ok(range);
+            }
+            timing_range(doc)
+        },
+        _ => Ok(None)
+    }
+}
+
+fn period_range(doc: &Doc) -> Result<Option<(i64, i64)>, EvalError> {
+    let start = doc.get_str("start")?;
+    let end = doc.get_str("end")?;
+    if start.is_none() && end.is_none() {
+        return Ok(None);
+    }
+
+    let low = match start {
+        Some(s) => to_date_range(s)?.0,
+        None => i64::MIN
+    };
+    let high = match end {
+        Some(e) => to_date_range(e)?.1,
+        None => i64::MAX
+    };
+
+    Ok(Some((low, high)))
+}
+
+/// the range of a Timing spans all of its events and the bounding period of the repetition
+fn timing_range(doc: &Doc) -> Result<Option<(i64, i64)>, EvalError> {
+    let mut range: Option<(i64, i64)> = None;
+    let mut extend = |r: (i64, i64)| {
+        range = Some(match range {
+            Some((low, high)) => (low.min(r.0), high.max(r.1)),
+            None => r
+        });
+    };
+
+    if let Some(events) = doc.get_array("event")? {
+        for e in events {
+            if let Some(e) = e?.as_str().ok() {
+                extend(to_date_range(e)?);
+            }
+        }
+    }
+
+    if let Some(repeat) = doc.get_document("repeat")? {
+        if let Some(bounds) = repeat.get_document("boundsPeriod")? {
+            if let Some(r) = period_range(bounds)? {
+                extend(r);
+            }
+        }
+    }
+
+    Ok(range)
+}
+
+fn to_millis(dt: &NaiveDateTime, offset: Option<FixedOffset>) -> i64 {
+    let local_millis = dt.timestamp() * 1000 + dt.timestamp_subsec_millis() as i64;
+    let offset_secs = match offset {
+        Some(o) => o.local_minus_utc(),
+        None => {
+            match Local.from_local_datetime(dt).earliest() {
+                Some(ldt) => ldt.offset().local_minus_utc(),
+                // the local time falls in a gap created by daylight saving changes
+                None => Local::now().offset().local_minus_utc()
+            }
+        }
+    };
+
+    local_millis - offset_secs as i64 * 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use chrono::Utc;
+    use rawbson::DocBuf;
+    use super::*;
+
+    fn millis(val: &str) -> i64 {
+        val.parse::<chrono::DateTime<Utc>>().unwrap().timestamp_millis()
+    }
+
+    #[test]
+    fn test_to_date_range() {
+        assert_eq!((millis("2021-01-01T10:30:00Z"), millis("2021-01-01T10:30:59.999Z")), to_date_range("2021-01-01T10:30Z").unwrap());
+        assert_eq!((millis("2021-01-01T10:30:05Z"), millis("2021-01-01T10:30:05.999Z")), to_date_range("2021-01-01T10:30:05Z").unwrap());
+        assert_eq!((millis("2021-01-01T10:30:05.100Z"), millis("2021-01-01T10:30:05.199Z")), to_date_range("2021-01-01T10:30:05.1Z").unwrap());
+        assert_eq!((millis("2021-01-01T05:00:00Z"), millis("2021-01-01T05:00:00.999Z")), to_date_range("2021-01-01T10:30:00+05:30").unwrap());
+
+        // the widths of the ranges are independent of the server's timezone
+        let (low, high) = to_date_range("1974").unwrap();
+        assert_eq!(millis("1975-01-01T00:00:00Z") - millis("1974-01-01T00:00:00Z") - 1, high - low);
+        let (low, high) = to_date_range("2020-02").unwrap();
+        assert_eq!(29 * 24 * 3600 * 1000 - 1, high - low);
+        let (low, high) = to_date_range("2020-12-31").unwrap();
+        assert_eq!(to_date_range("2020-12").unwrap().1, high);
+        assert!(low > to_date_range("2020-12-30").unwrap().1);
+
+        assert!(to_date_range("2020-13").is_err());
+        assert!(to_date_range("2020-02-30").is_err());
+        assert!(to_date_range("20201").is_err());
+        assert!(to_date_range("2020-01-01T10").is_err());
+    }
+
+    #[test]
+    fn test_element_to_date_range() {
+        let doc = DocBuf::from_document(&doc! {"start": "2021-01-01T00:00:00Z", "end": "2021-01-02T00:00:00Z"});
+        let el = Element::new(ElementType::EmbeddedDocument, doc.as_bytes());
+        assert_eq!(Some((millis("2021-01-01T00:00:00Z"), millis("2021-01-02T00:00:00.999Z"))), element_to_date_range(&el).unwrap());
+
+        let doc = DocBuf::from_document(&doc! {"start": "2021-01-01T00:00:00Z"});
+        let el = Element::new(ElementType::EmbeddedDocument, doc.as_bytes());
+        assert_eq!(Some((millis("2021-01-01T00:00:00Z"), i64::MAX)), element_to_date_range(&el).unwrap());
+
+        let doc = DocBuf::from_document(&doc! {"event": ["2021-03-01T00:00:00Z", "2021-01-01T00:00:00Z"], "repeat": {"boundsPeriod": {"end": "2021-06-01T00:00:00Z"}}});
+        let el = Element::new(ElementType::EmbeddedDocument, doc.as_bytes());
+        assert_eq!(Some((i64::MIN, millis("2021-06-01T00:00:00.999Z"))), element_to_date_range(&el).unwrap());
+
+        let doc = DocBuf::from_document(&doc! {"text": "no dates"});
+        let el = Element::new(ElementType::EmbeddedDocument, doc.as_bytes());
+        assert_eq!(None, element_to_date_range(&el).unwrap());
+    }
+}
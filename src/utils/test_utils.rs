@@ -1,6 +1,7 @@
 use std::borrow::BorrowMut;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fs;
 use std::fs::File;
 use std::net::Ipv4Addr;
 use std::path::PathBuf;
@@ -11,16 +12,22 @@ use bson::spec::ElementType;
 use rawbson::DocBuf;
 use rawbson::elem::Element;
 use rocket::{Build, Config, Rocket};
+use rocket::local::blocking::Client;
 use rocksdb::{DB, Options};
 use serde_json::{Map, Value};
-use crate::api::base::ApiBase;
+use crate::api::base::{ApiBase, RaResponse, ResponseHints, SearchQuery};
+use crate::api::bundle::SearchEntry;
 use crate::api::rest;
 use crate::barn::Barn;
-use crate::errors::RaError;
+use crate::config::Config as ApiConfig;
+use crate::errors::{EvalError, RaError};
 use crate::rapath::scanner::scan_tokens;
 use crate::rapath::expr::Ast;
 use crate::rapath::parser::parse;
 use crate::res_schema::SchemaDef;
+use crate::ResourceDef;
+use crate::search::executor::to_index_scanner;
+use crate::search::filter_converter::param_to_filter;
 
 pub struct TestContainer {
     path: PathBuf,
@@ -44,6 +51,11 @@ impl TestContainer {
     }
 
     pub fn create_server_with_example_patient(&self) -> Rocket<Build> {
+        self.create_server_with_config(ApiConfig::default())
+    }
+
+    /// same as create_server_with_example_patient but the API uses the given configuration
+    pub fn create_server_with_config(&self, mut api_config: ApiConfig) -> Rocket<Build> {
         if *self.initialized.borrow() {
             panic!("container was already initialized");
         }
@@ -52,13 +64,29 @@ impl TestContainer {
         config.address = Ipv4Addr::new(0,0,0,0).into();
         config.port = 7090;
         config.cli_colors = false;
-        let api_base = ApiBase::new(db, String::from("http://localhost:7090/")).unwrap();
+        api_config.base_url = String::from("http://localhost:7090/");
+        let api_base = ApiBase::new_with_config(db, api_config).unwrap();
         let data = read_patient_example();
         api_base.create("Patient", &data).expect("failed to insert example patient record");
         *self.initialized.borrow_mut() = true;
         rest::mount(api_base, config).unwrap()
     }
 
+    /// returns a client of the server having the example patient and the resources of bundle-example.json
+    pub fn create_client_with_example_bundle(&self) -> Client {
+        self.create_client_with_example_bundle_and_config(ApiConfig::default())
+    }
+
+    /// same as create_client_with_example_bundle but the API uses the given configuration
+    pub fn create_client_with_example_bundle_and_config(&self, api_config: ApiConfig) -> Client {
+        let r = self.create_server_with_config(api_config);
+        let client = Client::tracked(r).expect("create a HTTP client");
+        let bundle_file = fs::read("test_data/resources/bundle-example.json").unwrap();
+        let resp = client.post("/").body(bundle_file.as_slice()).dispatch();
+        assert_eq!(200, resp.status().code);
+        client
+    }
+
     pub fn setup_api_base_with_example_patient(&self) -> ApiBase {
         if *self.initialized.borrow() {
             panic!("container was already initialized");
@@ -111,11 +139,47 @@ pub fn read_patient_example() -> Value {
     serde_json::from_reader(f).expect("couldn't deserialize the example patient JSON")
 }
 
+/// creates the resource and returns its relative reference, e.g Patient/<id>
+pub fn create_resource(api_base: &ApiBase, res_name: &str, data: Value) -> Result<String, RaError> {
+    match api_base.create(res_name, &data)? {
+        RaResponse::Created(doc) => Ok(to_reference(&doc)),
+        _ => panic!("expected the resource to be created")
+    }
+}
+
+/// executes the query and returns the entries of the searchset
+pub fn search_entries(api_base: &ApiBase, res_name: &str, sq: &SearchQuery) -> Result<Vec<SearchEntry>, RaError> {
+    match api_base.search_query(res_name, sq, &ResponseHints::default())? {
+        RaResponse::SearchResult(ss) => Ok(ss.entries),
+        _ => panic!("expected a searchset")
+    }
+}
+
+/// returns the relative reference of the resource, e.g Patient/<id>
+pub fn to_reference(res: &Document) -> String {
+    format!("{}/{}", res.get_str("resourceType").unwrap(), res.get_str("id").unwrap())
+}
+
 pub fn to_docbuf(val: &Value) -> DocBuf {
     let doc = bson::to_bson(val).expect("failed to convert to Bson");
     DocBuf::from_document(doc.as_document().unwrap())
 }
 
+/// returns the number of resources matching the given value of the search parameter
+pub fn count_matches(name: &str, value: &str, rd: &ResourceDef, sd: &SchemaDef, db: &Barn) -> Result<usize, EvalError> {
+    let filter = param_to_filter(name, value, rd, sd)?;
+    let mut idx_scanner = to_index_scanner(&filter, rd, sd, db)?;
+    Ok(idx_scanner.collect_all().len())
+}
+
+/// returns the number of entries in the searchset returned for the given URL, e.g /Patient?birthdate=1974
+pub fn count_entries(client: &Client, url: &str) -> usize {
+    let resp = client.get(url).dispatch();
+    assert_eq!(200, resp.status().code, "{}", url);
+    let resp_val = resp.into_json::<Value>().unwrap();
+    resp_val.get("entry").map(|e| e.as_array().unwrap().len()).unwrap_or(0)
+}
+
 pub fn parse_expression(s: &str) -> Ast {
     let tokens = scan_tokens(s).unwrap();
     parse(tokens).unwrap()
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+
+pub const UCUM_SYSTEM: &str = "http://unitsofmeasure.org";
+
+lazy_static! {
+    /// maps a UCUM code to the canonical unit of its dimension and the factor to convert to it.
+    /// Only the units with a linear conversion are present (e.g Cel and [degF] are not)
+    static ref UNITS: HashMap<&'static str, (&'static str, f64)> = {
+        let mut units = HashMap::new();
+        // mass
+        units.insert("kg", ("g", 1e3));
+        units.insert("g", ("g", 1.0));
+        units.insert("mg", ("g", 1e-3));
+        units.insert("ug", ("g", 1e-6));
+        units.insert("ng", ("g", 1e-9));
+        units.insert("pg", ("g", 1e-12));
+        units.insert("[lb_av]", ("g", 453.59237));
+        units.insert("[oz_av]", ("g", 28.349523125));
+
+        // length
+        units.insert("km", ("m", 1e3));
+        units.insert("m", ("m", 1.0));
+        units.insert("dm", ("m", 1e-1));
+        units.insert("cm", ("m", 1e-2));
+        units.insert("mm", ("m", 1e-3));
+        units.insert("um", ("m", 1e-6));
+        units.insert("nm", ("m", 1e-9));
+        units.insert("[in_i]", ("m", 0.0254));
+        units.insert("[ft_i]", ("m", 0.3048));
+        units.insert("[mi_i]", ("m", 1609.344));
+
+        // volume
+        units.insert("L", ("L", 1.0));
+        units.insert("l", ("L", 1.0));
+        units.insert("dL", ("L", 1e-1));
+        units.insert("cL", ("L", 1e-2));
+        units.insert("mL", ("L", 1e-3));
+        units.insert("uL", ("L", 1e-6));
+        units.insert("nL", ("L", 1e-9));
+        units.insert("fL", ("L", 1e-15));
+        units.insert("m3", ("L", 1e3));
+        units.insert("cm3", ("L", 1e-3));
+        units.insert("[gal_us]", ("L", 3.785411784));
+
+        // time
+        units.insert("a", ("s", 31557600.0));
+        units.insert("mo", ("s", 2629800.0));
+        units.insert("wk", ("s", 604800.0));
+        units.insert("d", ("s", 86400.0));
+        units.insert("h", ("s", 3600.0));
+        units.insert("min", ("s", 60.0));
+        units.insert("s", ("s", 1.0));
+        units.insert("ms", ("s", 1e-3));
+        units.insert("us", ("s", 1e-6));
+
+        // frequency
+        units.insert("/s", ("/s", 1.0));
+        units.insert("/min", ("/s", 1.0 / 60.0));
+        units.insert("/h", ("/s", 1.0 / 3600.0));
+        units.insert("/d", ("/s", 1.0 / 86400.0));
+
+        // amount of substance
+        units.insert("mol", ("mol", 1.0));
+        units.insert("mmol", ("mol", 1e-3));
+        units.insert("umol", ("mol", 1e-6));
+        units.insert("nmol", ("mol", 1e-9));
+        units.insert("pmol", ("mol", 1e-12));
+
+        // mass concentration
+        units.insert("kg/L", ("g/L", 1e3));
+        units.insert("g/L", ("g/L", 1.0));
+        units.insert("g/dL", ("g/L", 1e1));
+        units.insert("g/mL", ("g/L", 1e3));
+        units.insert("mg/L", ("g/L", 1e-3));
+        units.insert("mg/dL", ("g/L", 1e-2));
+        units.insert("mg/mL", ("g/L", 1.0));
+        units.insert("ug/L", ("g/L", 1e-6));
+        units.insert("ug/dL", ("g/L", 1e-5));
+        units.insert("ug/mL", ("g/L", 1e-3));
+        units.insert("ng/L", ("g/L", 1e-9));
+        units.insert("ng/mL", ("g/L", 1e-6));
+        units.insert("pg/mL", ("g/L", 1e-9));
+
+        // substance concentration
+        units.insert("mol/L", ("mol/L", 1.0));
+        units.insert("mmol/L", ("mol/L", 1e-3));
+        units.insert("umol/L", ("mol/L", 1e-6));
+        units.insert("nmol/L", ("mol/L", 1e-9));
+        units.insert("pmol/L", ("mol/L", 1e-12));
+
+        // pressure
+        units.insert("Pa", ("Pa", 1.0));
+        units.insert("kPa", ("Pa", 1e3));
+        units.insert("bar", ("Pa", 1e5));
+        units.insert("mbar", ("Pa", 1e2));
+        units.insert("mm[Hg]", ("Pa", 133.322));
+        units.insert("cm[H2O]", ("Pa", 98.0665));
+        units.insert("[psi]", ("Pa", 6894.757293168361));
+
+        // energy
+        units.insert("J", ("J", 1.0));
+        units.insert("kJ", ("J", 1e3));
+        units.insert("cal", ("J", 4.184));
+        units.insert("kcal", ("J", 4184.0));
+        units
+    };
+}
+
+/// returns the canonical unit of the given UCUM code and the factor to convert a value to it
+pub fn canonical_unit(code: &str) -> Option<(&'static str, f64)> {
+    UNITS.get(code).copied()
+}
+
+/// converts the value to the canonical unit of its dimension, e.g 5.4 mg becomes 0.0054 g
+pub fn canonicalize(value: f64, code: &str) -> Option<(f64, &'static str)> {
+    canonical_unit(code).map(|(unit, factor)| (value * factor, unit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize() {
+        let (mg, unit) = canonicalize(5.4, "mg").unwrap();
+        assert_eq!("g", unit);
+        let (ug, _) = canonicalize(5400.0, "ug").unwrap();
+        assert!((mg - ug).abs() < 1e-12);
+
+        assert_eq!(Some((3600.0, "s")), canonicalize(1.0, "h"));
+        assert_eq!(Some((0.5, "L")), canonicalize(500.0, "mL"));
+        assert_eq!(None, canonicalize(37.0, "Cel"));
+        assert_eq!(None, canonicalize(1.0, "MG")); // UCUM codes are case sensitive
+    }
+}
@@ -0,0 +1,370 @@
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
+use serde_json::Value;
+use ra_registry::config::Config;
+use ra_registry::utils::test_utils::*;
+
+#[test]
+fn test_read() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let patient = std::fs::read("test_data/resources/patient-example.json").unwrap();
+    let resp = client.post("/Patient").body(patient.as_slice()).dispatch();
+    assert_eq!(Status::Created, resp.status());
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    let id = location.split("/").nth(1).unwrap();
+
+    let resp = client.get(format!("/Patient/{}", id)).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    assert_eq!(Some("W/\"1\""), resp.headers().get_one("ETag"));
+    assert!(resp.headers().get_one("Last-Modified").is_some());
+
+    let resp = client.get(format!("/{}", location)).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+
+    let resp = client.get(format!("/Patient/{}/_history/2", id)).dispatch();
+    assert_eq!(Status::NotFound, resp.status());
+
+    let resp = client.get("/Patient/unknown").dispatch();
+    assert_eq!(Status::NotFound, resp.status());
+}
+
+#[test]
+fn test_update() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let mut patient = read_patient_example();
+    let resp = client.post("/Patient").body(patient.to_string()).dispatch();
+    assert_eq!(Status::Created, resp.status());
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    let id = location.split("/").nth(1).unwrap().to_string();
+
+    patient.as_object_mut().unwrap().insert(String::from("id"), Value::String(id.clone()));
+    patient.as_object_mut().unwrap().insert(String::from("gender"), Value::String(String::from("female")));
+    let resp = client.put(format!("/Patient/{}", id)).body(patient.to_string()).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    assert_eq!(Some(format!("Patient/{}/_history/2", id).as_str()), resp.headers().get_one("Location"));
+
+    let resp = client.get(format!("/Patient/{}", id)).dispatch();
+    assert_eq!(Some("W/\"2\""), resp.headers().get_one("ETag"));
+    let resp_val = resp.into_json::<Value>().unwrap();
+    assert_eq!("female", resp_val.get("gender").unwrap().as_str().unwrap());
+
+    let resp = client.get(format!("/Patient/{}/_history/1", id)).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+}
+
+#[test]
+fn test_update_create_with_client_id() {
+    let tc = TestContainer::new();
+    let mut config = Config::default();
+    config.update_create = true;
+    let r = tc.create_server_with_config(config);
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let mut patient = read_patient_example();
+    patient.as_object_mut().unwrap().insert(String::from("id"), Value::String(String::from("pat-1")));
+    let resp = client.put("/Patient/pat-1").body(patient.to_string()).dispatch();
+    assert_eq!(Status::Created, resp.status());
+    assert_eq!(Some("Patient/pat-1/_history/1"), resp.headers().get_one("Location"));
+
+    let bundle = serde_json::json!({
+        "resourceType": "Bundle",
+        "type": "transaction",
+        "entry": [
+            {
+                "resource": {"resourceType": "Observation", "id": "obs-1", "status": "final", "code": {"text": "weight"}, "subject": {"reference": "Patient/pat-1"}},
+                "request": {"method": "PUT", "url": "Observation/obs-1"}
+            }
+        ]
+    });
+    let resp = client.post("/").body(bundle.to_string()).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    let bundle = resp.into_json::<Value>().unwrap();
+    assert_eq!("201 Created", bundle.pointer("/entry/0/response/status").unwrap().as_str().unwrap());
+    assert_eq!("Observation/obs-1/_history/1", bundle.pointer("/entry/0/response/location").unwrap().as_str().unwrap());
+
+    let resp = client.get("/Observation/obs-1").dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    let resp = client.get("/Patient/pat-1").dispatch();
+    assert_eq!(Status::Ok, resp.status());
+}
+
+#[test]
+fn test_delete() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let patient = read_patient_example();
+    let resp = client.post("/Patient").body(patient.to_string()).dispatch();
+    assert_eq!(Status::Created, resp.status());
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    let id = location.split("/").nth(1).unwrap().to_string();
+
+    let resp = client.delete(format!("/Patient/{}", id)).dispatch();
+    assert_eq!(Status::NoContent, resp.status());
+
+    let resp = client.get(format!("/Patient/{}", id)).dispatch();
+    assert_eq!(Status::Gone, resp.status());
+
+    let resp = client.get(format!("/Patient/{}/_history/1", id)).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+}
+
+#[test]
+fn test_history() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let patient = read_patient_example();
+    let resp = client.post("/Patient").body(patient.to_string()).dispatch();
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    let id = location.split("/").nth(1).unwrap().to_string();
+    let resp = client.delete(format!("/Patient/{}", id)).dispatch();
+    assert_eq!(Status::NoContent, resp.status());
+
+    let resp = client.get(format!("/Patient/{}/_history", id)).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    let bundle = resp.into_json::<Value>().unwrap();
+    assert_eq!("history", bundle.get("type").unwrap().as_str().unwrap());
+    assert_eq!(2, bundle.get("entry").unwrap().as_array().unwrap().len());
+    assert_eq!("DELETE", bundle.pointer("/entry/0/request/method").unwrap().as_str().unwrap());
+    assert_eq!(format!("Patient/{}", id), bundle.pointer("/entry/0/request/url").unwrap().as_str().unwrap());
+    assert_eq!(format!("http://localhost:7090/Patient/{}", id), bundle.pointer("/entry/0/fullUrl").unwrap().as_str().unwrap());
+    assert!(bundle.pointer("/entry/0/resource").is_none());
+    assert_eq!("POST", bundle.pointer("/entry/1/request/method").unwrap().as_str().unwrap());
+
+    let resp = client.get("/Patient/_history?_count=1").dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    let bundle = resp.into_json::<Value>().unwrap();
+    assert_eq!(1, bundle.get("entry").unwrap().as_array().unwrap().len());
+
+    let resp = client.get("/_history?_since=2000-01-01").dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    let bundle = resp.into_json::<Value>().unwrap();
+    assert_eq!(3, bundle.get("entry").unwrap().as_array().unwrap().len());
+}
+
+#[test]
+fn test_conditional_create() {
+    let tc = TestContainer::new();
+    let mut config = Config::default();
+    config.conditional_create = true;
+    let r = tc.create_server_with_config(config);
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let patient = read_patient_example();
+    let resp = client.post("/Patient").header(Header::new("If-None-Exist", "family=Chalmers")).body(patient.to_string()).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+
+    let resp = client.post("/Patient").header(Header::new("If-None-Exist", "family=Someone-Else")).body(patient.to_string()).dispatch();
+    assert_eq!(Status::Created, resp.status());
+
+    let resp = client.post("/Patient").header(Header::new("If-None-Exist", "family=Chalmers")).body(patient.to_string()).dispatch();
+    assert_eq!(Status::PreconditionFailed, resp.status());
+}
+
+#[test]
+fn test_conditional_update_and_delete() {
+    let tc = TestContainer::new();
+    let mut config = Config::default();
+    config.conditional_update = true;
+    config.conditional_delete = true;
+    let r = tc.create_server_with_config(config);
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let mut patient = read_patient_example();
+    patient.as_object_mut().unwrap().remove("id");
+    let resp = client.put("/Patient?family=Chalmers").body(patient.to_string()).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    assert!(location.ends_with("/_history/2"));
+
+    let resp = client.delete("/Patient?family=Chalmers").dispatch();
+    assert_eq!(Status::NoContent, resp.status());
+
+    let resp = client.put("/Patient?family=Chalmers").body(patient.to_string()).dispatch();
+    assert_eq!(Status::Created, resp.status());
+}
+
+#[test]
+fn test_if_match() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let mut patient = read_patient_example();
+    let resp = client.post("/Patient").body(patient.to_string()).dispatch();
+    assert_eq!(Some("W/\"1\""), resp.headers().get_one("ETag"));
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    let id = location.split("/").nth(1).unwrap().to_string();
+
+    patient.as_object_mut().unwrap().insert(String::from("id"), Value::String(id.clone()));
+    let resp = client.put(format!("/Patient/{}", id)).header(Header::new("If-Match", "W/\"1\"")).body(patient.to_string()).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    assert_eq!(Some("W/\"2\""), resp.headers().get_one("ETag"));
+
+    let resp = client.put(format!("/Patient/{}", id)).header(Header::new("If-Match", "W/\"1\"")).body(patient.to_string()).dispatch();
+    assert_eq!(Status::PreconditionFailed, resp.status());
+
+    let resp = client.delete(format!("/Patient/{}", id)).header(Header::new("If-Match", "W/\"1\"")).dispatch();
+    assert_eq!(Status::PreconditionFailed, resp.status());
+
+    let resp = client.delete(format!("/Patient/{}", id)).header(Header::new("If-Match", "W/\"2\"")).dispatch();
+    assert_eq!(Status::NoContent, resp.status());
+}
+
+#[test]
+fn test_conditional_read() {
+    let tc = TestContainer::new();
+    let mut config = Config::default();
+    config.conditional_read = true;
+    let r = tc.create_server_with_config(config);
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let patient = read_patient_example();
+    let resp = client.post("/Patient").body(patient.to_string()).dispatch();
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    let id = location.split("/").nth(1).unwrap().to_string();
+
+    let resp = client.get(format!("/Patient/{}", id)).header(Header::new("If-None-Match", "W/\"1\"")).dispatch();
+    assert_eq!(Status::NotModified, resp.status());
+    assert_eq!(Some("W/\"1\""), resp.headers().get_one("ETag"));
+    assert!(resp.into_bytes().unwrap_or_default().is_empty());
+
+    let resp = client.get(format!("/Patient/{}", id)).dispatch();
+    let last_modified = resp.headers().get_one("Last-Modified").unwrap().to_string();
+    let resp = client.get(format!("/Patient/{}/_history/1", id)).header(Header::new("If-Modified-Since", last_modified)).dispatch();
+    assert_eq!(Status::NotModified, resp.status());
+
+    let resp = client.get(format!("/Patient/{}", id)).header(Header::new("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+}
+
+#[test]
+fn test_patch() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let patient = read_patient_example();
+    let resp = client.post("/Patient").body(patient.to_string()).dispatch();
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    let id = location.split("/").nth(1).unwrap().to_string();
+
+    let patch = r#"[{"op": "replace", "path": "/gender", "value": "female"}]"#;
+    let resp = client.patch(format!("/Patient/{}", id)).header(Header::new("Content-Type", "application/json-patch+json")).body(patch).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    assert_eq!(Some("W/\"2\""), resp.headers().get_one("ETag"));
+
+    let resp = client.get(format!("/Patient/{}", id)).dispatch();
+    let resp_val = resp.into_json::<Value>().unwrap();
+    assert_eq!("female", resp_val.get("gender").unwrap().as_str().unwrap());
+
+    let resp = client.patch(format!("/Patient/{}", id)).header(Header::new("If-Match", "W/\"1\"")).body(patch).dispatch();
+    assert_eq!(Status::PreconditionFailed, resp.status());
+
+    let patch = r#"[{"op": "test", "path": "/gender", "value": "male"}]"#;
+    let resp = client.patch(format!("/Patient/{}", id)).body(patch).dispatch();
+    assert_eq!(Status::PreconditionFailed, resp.status());
+}
+
+#[test]
+fn test_fhirpath_patch() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let patient = read_patient_example();
+    let resp = client.post("/Patient").body(patient.to_string()).dispatch();
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    let id = location.split("/").nth(1).unwrap().to_string();
+
+    let patch = r#"{"resourceType": "Parameters", "parameter": [{"name": "operation", "part": [
+        {"name": "type", "valueCode": "replace"},
+        {"name": "path", "valueString": "Patient.name.where(use = 'official').family"},
+        {"name": "value", "valueString": "Windsor"}]}]}"#;
+    let resp = client.patch(format!("/Patient/{}", id)).body(patch).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+
+    let resp = client.get(format!("/Patient/{}", id)).dispatch();
+    let resp_val = resp.into_json::<Value>().unwrap();
+    assert_eq!("Windsor", resp_val.pointer("/name/0/family").unwrap().as_str().unwrap());
+}
+
+#[test]
+fn test_batch() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let patient = read_patient_example();
+    let bundle = serde_json::json!({
+        "resourceType": "Bundle",
+        "type": "batch",
+        "entry": [
+            {"resource": patient, "request": {"method": "POST", "url": "Patient"}},
+            {"request": {"method": "GET", "url": "Patient/unknown"}}
+        ]
+    });
+    let resp = client.post("/").body(bundle.to_string()).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    let bundle = resp.into_json::<Value>().unwrap();
+    assert_eq!("batch-response", bundle.get("type").unwrap().as_str().unwrap());
+    assert_eq!("201 Created", bundle.pointer("/entry/0/response/status").unwrap().as_str().unwrap());
+    assert!(bundle.pointer("/entry/1/response/status").unwrap().as_str().unwrap().starts_with("404"));
+}
+
+#[test]
+fn test_transaction_response() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let bundle = std::fs::read("test_data/resources/bundle-example.json").unwrap();
+    let resp = client.post("/").header(Header::new("Prefer", "return=representation")).body(bundle.as_slice()).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    let bundle = resp.into_json::<Value>().unwrap();
+    assert_eq!("transaction-response", bundle.get("type").unwrap().as_str().unwrap());
+    let entries = bundle.get("entry").unwrap().as_array().unwrap();
+    assert!(!entries.is_empty());
+    for e in entries {
+        assert_eq!("201 Created", e.pointer("/response/status").unwrap().as_str().unwrap());
+        let id = e.pointer("/resource/id").unwrap().as_str().unwrap();
+        let location = e.pointer("/response/location").unwrap().as_str().unwrap();
+        assert_eq!(format!("{}/{}/_history/1", e.pointer("/resource/resourceType").unwrap().as_str().unwrap(), id), location);
+    }
+}
+
+#[test]
+fn test_transaction_with_conditional_refs() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+
+    let bundle = serde_json::json!({
+        "resourceType": "Bundle",
+        "type": "transaction",
+        "entry": [
+            {"request": {"method": "GET", "url": "Patient?family=Chalmers"}},
+            {
+                "resource": {"resourceType": "Observation", "status": "final", "code": {"text": "weight"}, "subject": {"reference": "Patient?family=Chalmers"}},
+                "request": {"method": "POST", "url": "Observation"}
+            }
+        ]
+    });
+    let resp = client.post("/").header(Header::new("Prefer", "return=representation")).body(bundle.to_string()).dispatch();
+    assert_eq!(Status::Ok, resp.status());
+    let bundle = resp.into_json::<Value>().unwrap();
+    assert_eq!("200 OK", bundle.pointer("/entry/0/response/status").unwrap().as_str().unwrap());
+    assert_eq!("201 Created", bundle.pointer("/entry/1/response/status").unwrap().as_str().unwrap());
+    let reference = bundle.pointer("/entry/1/resource/subject/reference").unwrap().as_str().unwrap();
+    assert!(reference.starts_with("Patient/"));
+    assert!(!reference.contains('?'));
+}
@@ -4,6 +4,7 @@ use std::io::Read;
 use rocket::http::Header;
 use rocket::local::blocking::Client;
 use serde_json::Value;
+use ra_registry::config::Config;
 use ra_registry::utils::test_utils::*;
 
 #[test]
@@ -19,9 +20,10 @@ fn test_query() {
     let resp = client.get("/Patient?name=Windsor").dispatch();
     assert_eq!(200, resp.status().code);
     let resp_val = resp.into_json::<Value>().unwrap();
-    assert_eq!(1, resp_val.get("count").unwrap().as_i64().unwrap());
+    assert_eq!(1, resp_val.get("entry").unwrap().as_array().unwrap().len());
+    assert!(resp_val.get("total").is_none());
     assert_eq!("searchset", resp_val.get("type").unwrap().as_str().unwrap());
-    assert_eq!("1974-12-25T14:35:45-05:00", resp_val.pointer("/entries/0/resource/_birthDate/extension/0/valueDateTime").unwrap().as_str().unwrap());
+    assert_eq!("1974-12-25T14:35:45-05:00", resp_val.pointer("/entry/0/resource/_birthDate/extension/0/valueDateTime").unwrap().as_str().unwrap());
 
     let search_req = client.get("/Patient?unknown-search-param=1&name=Windsor").header(Header::new("Prefer", "handling=lenient"));
     let resp = search_req.dispatch();
@@ -37,10 +39,141 @@ fn test_query() {
     let resp = client.get("/Patient?name=Windsor,Dusti191").dispatch();
     assert_eq!(200, resp.status().code);
     let resp_val = resp.into_json::<Value>().unwrap();
-    assert_eq!(2, resp_val.get("count").unwrap().as_i64().unwrap());
+    assert_eq!(2, resp_val.get("entry").unwrap().as_array().unwrap().len());
 
     let resp = client.get("/Patient?name=Windsor,Dusti191&name=xyz").dispatch();
     assert_eq!(200, resp.status().code);
     let resp_val = resp.into_json::<Value>().unwrap();
-    assert_eq!(0, resp_val.get("count").unwrap().as_i64().unwrap());
-}
\ No newline at end of file
+    assert!(resp_val.get("entry").is_none());
+}
+
+#[test]
+fn test_total() {
+    let tc = TestContainer::new();
+    let client = tc.create_client_with_example_bundle();
+
+    let resp = client.get("/Patient?name=Windsor,Dusti191&_total=accurate&_count=1").dispatch();
+    assert_eq!(200, resp.status().code);
+    let resp_val = resp.into_json::<Value>().unwrap();
+    assert_eq!(2, resp_val.get("total").unwrap().as_i64().unwrap());
+    assert_eq!(1, resp_val.get("entry").unwrap().as_array().unwrap().len());
+    let links = resp_val.get("link").unwrap().as_array().unwrap();
+    assert_eq!("self", links[0].get("relation").unwrap().as_str().unwrap());
+    assert_eq!("next", links[1].get("relation").unwrap().as_str().unwrap());
+
+    let resp = client.get("/Patient?name=Windsor,Dusti191&_total=estimate").dispatch();
+    let resp_val = resp.into_json::<Value>().unwrap();
+    assert_eq!(2, resp_val.get("total").unwrap().as_i64().unwrap());
+
+    let resp = client.get("/Patient?name=Windsor,Dusti191&_summary=count").dispatch();
+    assert_eq!(200, resp.status().code);
+    let resp_val = resp.into_json::<Value>().unwrap();
+    assert_eq!(2, resp_val.get("total").unwrap().as_i64().unwrap());
+    assert!(resp_val.get("entry").is_none());
+
+    // the estimate is the number of patients, the search parameters are not evaluated
+    let resp = client.get("/Patient?name=Windsor&_summary=count&_total=estimate").dispatch();
+    assert_eq!(200, resp.status().code);
+    let resp_val = resp.into_json::<Value>().unwrap();
+    assert_eq!(2, resp_val.get("total").unwrap().as_i64().unwrap());
+    assert!(resp_val.get("entry").is_none());
+}
+
+#[test]
+fn test_include() {
+    let tc = TestContainer::new();
+    let mut config = Config::default();
+    config.search_include = true;
+    config.search_rev_include = true;
+    let client = tc.create_client_with_example_bundle_and_config(config);
+
+    let resp = client.get("/Encounter?status=finished&_include=Encounter:subject&_include=Encounter:practitioner").dispatch();
+    assert_eq!(200, resp.status().code);
+    let resp_val = resp.into_json::<Value>().unwrap();
+    let entries = resp_val.get("entry").unwrap().as_array().unwrap();
+    assert_eq!(3, entries.len());
+    assert_eq!("Encounter", entries[0].pointer("/resource/resourceType").unwrap().as_str().unwrap());
+    assert_eq!("match", entries[0].pointer("/search/mode").unwrap().as_str().unwrap());
+    assert_eq!("Patient", entries[1].pointer("/resource/resourceType").unwrap().as_str().unwrap());
+    assert_eq!("include", entries[1].pointer("/search/mode").unwrap().as_str().unwrap());
+    assert_eq!("Practitioner", entries[2].pointer("/resource/resourceType").unwrap().as_str().unwrap());
+    assert_eq!("include", entries[2].pointer("/search/mode").unwrap().as_str().unwrap());
+    let self_link = resp_val.pointer("/link/0/url").unwrap().as_str().unwrap();
+    assert!(self_link.contains("_include=Encounter%3Asubject&_include=Encounter%3Apractitioner"));
+
+    let resp = client.get("/Patient?name=Dusti191&_revinclude=Encounter:subject&_include:iterate=Encounter:service-provider").dispatch();
+    assert_eq!(200, resp.status().code);
+    let resp_val = resp.into_json::<Value>().unwrap();
+    let entries = resp_val.get("entry").unwrap().as_array().unwrap();
+    let res_types: Vec<&str> = entries.iter().map(|e| e.pointer("/resource/resourceType").unwrap().as_str().unwrap()).collect();
+    assert_eq!(vec!["Patient", "Encounter", "Organization"], res_types);
+
+    let resp = client.get("/Encounter?status=finished&_include=Encounter:unknown").dispatch();
+    assert_eq!(400, resp.status().code);
+}
+
+#[test]
+fn test_reverse_chaining() {
+    let tc = TestContainer::new();
+    let client = tc.create_client_with_example_bundle();
+
+    let resp = client.get("/Patient?_has:Encounter:subject:status=finished").dispatch();
+    assert_eq!(200, resp.status().code);
+    let resp_val = resp.into_json::<Value>().unwrap();
+    let entries = resp_val.get("entry").unwrap().as_array().unwrap();
+    assert_eq!(1, entries.len());
+    assert_eq!("Osinski784", entries[0].pointer("/resource/name/0/family").unwrap().as_str().unwrap());
+
+    let resp = client.get("/Patient?_has:Encounter:status:status=finished").dispatch();
+    assert_eq!(400, resp.status().code);
+}
+
+#[test]
+fn test_date_search() {
+    let tc = TestContainer::new();
+    let client = tc.create_client_with_example_bundle();
+
+    let candidates = [("1974", 1), ("1974-12", 1), ("1974-12-25", 1), ("1974-12-24", 0), ("ge1974-12-25", 2),
+        ("gt1974", 1), ("lt1997-01-15", 1), ("le1997-01-15", 2), ("sa1974-12-25", 1), ("eb1997", 1), ("ne1974", 1)];
+    for (date, expected) in candidates {
+        assert_eq!(expected, count_entries(&client, &format!("/Patient?birthdate={}", date)), "birthdate={}", date);
+    }
+
+    let resp = client.get("/Patient?birthdate=1974-13").dispatch();
+    assert_eq!(400, resp.status().code);
+}
+
+#[test]
+fn test_quantity_search() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+    for (value, code) in [(5400, "ug"), (3, "mg")] {
+        let obs = format!(r#"{{"resourceType": "Observation", "status": "final", "code": {{"text": "dose"}}, "valueQuantity": {{"value": {}, "unit": "{}", "system": "http://unitsofmeasure.org", "code": "{}"}}}}"#, value, code, code);
+        let resp = client.post("/Observation").body(obs).dispatch();
+        assert_eq!(201, resp.status().code);
+    }
+
+    let candidates = [("5.4|http://unitsofmeasure.org|mg", 1), ("5.4||mg", 1), ("gt0.001|http://unitsofmeasure.org|g", 2), ("lt5|http://unitsofmeasure.org|mg", 1), ("5400", 1)];
+    for (quantity, expected) in candidates {
+        assert_eq!(expected, count_entries(&client, &format!("/Observation?value-quantity={}", quantity.replace('|', "%7C"))), "value-quantity={}", quantity);
+    }
+}
+
+#[test]
+fn test_uri_search() {
+    let tc = TestContainer::new();
+    let r = tc.create_server_with_example_patient();
+    let client = Client::tracked(r).expect("create a HTTP client");
+    for url in ["http://acme.org/fhir/ValueSet/123", "http://acme.org/fhir/ValueSet/456"] {
+        let vs = format!(r#"{{"resourceType": "ValueSet", "status": "active", "url": "{}"}}"#, url);
+        let resp = client.post("/ValueSet").body(vs).dispatch();
+        assert_eq!(201, resp.status().code);
+    }
+
+    let candidates = [("url=http://acme.org/fhir/ValueSet/123", 1), ("url:below=http://acme.org/fhir", 2),
+        ("url:above=http://acme.org/fhir/ValueSet/456/_history/1", 1), ("url=http://acme.org/fhir", 0)];
+    for (param, expected) in candidates {
+        assert_eq!(expected, count_entries(&client, &format!("/ValueSet?{}", param)), "{}", param);
+    }
+}